@@ -21,6 +21,47 @@ pub fn build_app() -> App<'static, 'static> {
                 .takes_value(true)
                 .help("Sets the config file to use"),
         )
+        .arg(
+            Arg::with_name("preferences")
+                .short("p")
+                .long("preferences")
+                .takes_value(true)
+                .help("Sets the file with the time preferences of the attendees"),
+        )
+        .arg(
+            Arg::with_name("solver")
+                .short("s")
+                .long("solver")
+                .takes_value(true)
+                .possible_values(&["cbc", "builtin"])
+                .help("Sets the solver to use, defaults to cbc if it is installed"),
+        )
+        .arg(
+            Arg::with_name("fetch")
+                .long("fetch")
+                .takes_value(true)
+                .possible_values(&["events", "freebusy"])
+                .help("Sets how to read the calendars, defaults to their events"),
+        )
+        .arg(
+            Arg::with_name("best-effort")
+                .long("best-effort")
+                .help("Books as many meetings as possible instead of failing when one cannot be scheduled"),
+        )
+        .arg(
+            Arg::with_name("export")
+                .short("e")
+                .long("export")
+                .takes_value(true)
+                .conflicts_with("book")
+                .help("Writes the meetings as invites to this file instead of booking them"),
+        )
+        .arg(
+            Arg::with_name("organizer")
+                .long("organizer")
+                .takes_value(true)
+                .help("Sets the organizer of the exported invites, they are published without one"),
+        )
         .arg(
             Arg::with_name("book")
                 .short("b")
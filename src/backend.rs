@@ -0,0 +1,241 @@
+// What optirust needs from a calendar provider: reading the calendars of the
+// attendees and booking the meetings. Each provider implements
+// CalendarBackend, the rest of the code only deals with the trait.
+use std::collections::HashMap;
+use std::ops::Range;
+use chrono;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use fixtures;
+use caldav;
+use gcal;
+use graph;
+use ical;
+use ics;
+use types::{CalendarSettings, Config, DesiredMeeting, MeetingCandidate, MeetingsTree, Room,
+            Solution};
+
+const TAGLINE: &str = "=> Booked by Optirust: https://github.com/charignon/optirust";
+
+// An event to create in the calendars, whatever the provider
+#[derive(Debug, PartialEq, Clone)]
+pub struct NewEvent {
+    pub title: String,
+    pub description: String,
+    pub attendees: Vec<String>,
+    // Invited but not required
+    pub optional_attendees: Vec<String>,
+    pub room: Option<String>,
+    pub start: DateTime<chrono::Utc>,
+    pub end: DateTime<chrono::Utc>,
+    pub timezone: Tz,
+    // RRULE of a recurring event
+    pub recurrence: Option<String>,
+}
+
+pub trait CalendarBackend {
+    // The existing meetings of the people within the window
+    fn fetch_availability(
+        &self,
+        people: Vec<String>,
+        window: &Range<DateTime<chrono::Utc>>,
+        ignore_all_day_events: bool,
+        ignore_meetings_with_no_response: bool,
+    ) -> HashMap<String, MeetingsTree>;
+
+    // What the calendars of the people say about their time zone and working
+    // hours, people the provider knows nothing about are left out. The window
+    // is the one the availability is fetched for next, providers reading the
+    // settings from the events can keep them for it
+    fn fetch_settings(
+        &self,
+        people: Vec<String>,
+        window: &Range<DateTime<chrono::Utc>>,
+    ) -> HashMap<String, CalendarSettings>;
+
+    // Returns the ids of the created events, in order
+    fn create_events(&self, events: &[NewEvent]) -> Vec<String>;
+
+    // Optirust only creates events for now, these are for the callers of the
+    // backends
+    #[allow(dead_code)]
+    fn update_event(&self, id: &str, event: &NewEvent);
+
+    #[allow(dead_code)]
+    fn delete_event(&self, id: &str);
+
+    // The rooms that can be booked
+    fn list_rooms(&self) -> Vec<Room>;
+
+    // Whether the meetings can be booked at all, checked before fetching
+    fn can_book(&self) -> bool {
+        true
+    }
+}
+
+// Return the backend named in the config, google calendar by default.
+// freebusy only reads the busy times of the calendars.
+pub fn backend_from_config(config: Option<&Config>, freebusy: bool) -> Box<CalendarBackend> {
+    match config.and_then(|k| k.backend.as_deref()) {
+        Some("google") | None => Box::new(gcal::GoogleCalendar::new(freebusy)),
+        Some("caldav") => Box::new(caldav::CalDav {
+            config: config
+                .and_then(|k| k.caldav.clone())
+                .expect("The caldav backend needs a caldav section in the config"),
+            freebusy,
+        }),
+        Some("graph") => Box::new(graph::Graph {
+            config: config
+                .and_then(|k| k.graph.clone())
+                .expect("The graph backend needs a graph section in the config"),
+        }),
+        Some("ics") => Box::new(ics::IcsFiles {
+            config: config
+                .and_then(|k| k.ics.clone())
+                .expect("The ics backend needs an ics section in the config"),
+        }),
+        Some(k) => panic!("Unknown backend {:?}", k),
+    }
+}
+
+fn meeting_event(
+    desired_meeting: &DesiredMeeting,
+    candidate: &MeetingCandidate,
+    include_tagline: bool,
+) -> NewEvent {
+    let description = if include_tagline {
+        format!("{}\n{}", desired_meeting.description, TAGLINE)
+    } else {
+        desired_meeting.description.clone()
+    };
+    NewEvent {
+        title: desired_meeting.title.to_string(),
+        description,
+        // The candidate knows who was picked from the pools
+        attendees: candidate.attendees.clone(),
+        optional_attendees: desired_meeting.optional_attendees.clone(),
+        room: candidate.room.clone(),
+        start: candidate.start,
+        end: candidate.end,
+        timezone: desired_meeting.timezone,
+        recurrence: desired_meeting.recurrence.as_ref().map(|r| r.to_rrule()),
+    }
+}
+
+// The events to create for a solution. Every session of an interview loop is
+// its own event in the room of the loop, with the attendees of the loop and
+// the interviewers of the session.
+pub fn solution_events(s: &Solution, include_tagline: bool) -> Vec<NewEvent> {
+    let mut res = Vec::new();
+    for (desired_meeting, candidate) in &s.candidates {
+        let event = meeting_event(desired_meeting, candidate, include_tagline);
+        if candidate.sessions.is_empty() {
+            res.push(event.clone());
+        }
+        for session in &candidate.sessions {
+            let mut attendees = event.attendees.clone();
+            attendees.extend(session.interviewers.iter().cloned());
+            res.push(NewEvent {
+                title: format!("{}: {}", desired_meeting.title, session.title),
+                attendees,
+                start: session.start,
+                end: session.end,
+                ..event.clone()
+            });
+        }
+    }
+    res
+}
+
+pub fn book(backend: &CalendarBackend, s: &Solution, include_tagline: bool) -> Vec<String> {
+    backend.create_events(&solution_events(s, include_tagline))
+}
+
+// The events of a solution as an iCalendar file of invites
+pub fn export(s: &Solution, organizer: Option<&str>, include_tagline: bool) -> String {
+    let now = chrono::Utc::now();
+    let events = solution_events(s, include_tagline);
+    let events = events
+        .iter()
+        .enumerate()
+        .map(|(k, e)| (ical::new_uid(&now, k), e))
+        .collect::<Vec<(String, &NewEvent)>>();
+    ical::invites(&events, organizer, &now)
+}
+
+#[test]
+fn test_book_loop_sessions() {
+    let backend = fixtures::FakeBackend::new(fixtures::fetch_results);
+    let (_, candidates) =
+        fixtures::solve_input(&fixtures::test_loop_input(), fixtures::test_options());
+    let solution = Solution {
+        solved: true,
+        candidates,
+        unscheduled: HashMap::new(),
+    };
+    let ids = book(&backend, &solution, false);
+    assert_eq!(ids.len(), 2);
+
+    // One event per session, back to back, with the interviewers of the
+    // session on top of the attendees of the loop
+    let mut events = backend.created.borrow().clone();
+    events.sort_by_key(|k| k.start);
+    assert_eq!(events[0].end, events[1].start);
+    for e in &events {
+        assert!(e.title.starts_with("onsite: "));
+        assert!(e.attendees.len() > solution.candidates.values().next().unwrap().attendees.len());
+        assert_eq!(e.recurrence, None);
+    }
+}
+
+#[test]
+fn test_export_solution() {
+    use types::Options;
+    let options = Options {
+        room_picker_fn: Box::new(|_, _| Some(vec!["four@jam.com".to_string()])),
+        ..fixtures::test_options()
+    };
+    let (_, candidates) = fixtures::solve_input(&fixtures::test_input(), options);
+    let solution = Solution {
+        solved: true,
+        candidates,
+        unscheduled: HashMap::new(),
+    };
+    let invites = ical::parse(&export(&solution, Some("me@foo.com"), false));
+    let calendar = &invites.components[0];
+    assert_eq!(calendar.property("METHOD").unwrap().value, "REQUEST");
+
+    // One invite per meeting, from the organizer, in its room
+    let events = calendar.find_all("VEVENT");
+    assert_eq!(events.len(), solution.candidates.len());
+    for e in events {
+        assert_eq!(e.property("ORGANIZER").unwrap().value, "mailto:me@foo.com");
+        let (desired_meeting, candidate) = solution
+            .candidates
+            .iter()
+            .find(|k| k.0.title == e.property("SUMMARY").unwrap().value)
+            .unwrap();
+        assert_eq!(e.event_bounds(&Tz::UTC), (candidate.start, candidate.end));
+        assert_eq!(
+            e.property("DESCRIPTION").unwrap().value,
+            desired_meeting.description.replace('\n', "\\n")
+        );
+        let room = e.properties_named("ATTENDEE")
+            .find(|k| k.param("CUTYPE") == Some("ROOM"))
+            .map(|k| k.value.trim_start_matches("mailto:").to_string());
+        assert_eq!(room, Some("four@jam.com".to_string()));
+        assert_eq!(room, candidate.room);
+        assert_eq!(e.property("LOCATION").map(|k| k.value.to_string()), candidate.room);
+        assert_eq!(
+            e.properties_named("ATTENDEE").count(),
+            candidate.attendees.len() + desired_meeting.optional_attendees.len()
+                + room.iter().count()
+        );
+    }
+
+    // Published without an organizer
+    let published = ical::parse(&export(&solution, None, false));
+    let calendar = &published.components[0];
+    assert_eq!(calendar.property("METHOD").unwrap().value, "PUBLISH");
+    assert!(calendar.find_all("VEVENT").iter().all(|k| k.property("ORGANIZER").is_none()));
+}
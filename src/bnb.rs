@@ -0,0 +1,395 @@
+// Builtin solver for 0-1 integer linear problems, used when cbc is not
+// installed. It is a depth first branch and bound: branches are explored best
+// candidate first, so the first solution found is the greedy one and it is
+// used to prune the rest of the search.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use solver::{Problem, Relation};
+
+// Past this number of nodes we stop and return the best solution found so far
+const NODE_LIMIT: usize = 5_000_000;
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    // The objective and the set of variables equal to 1 in the best solution
+    Optimal(i64, HashSet<String>),
+    // The search stopped at the node limit, with the best solution found so
+    // far if any. Without a solution we do not know whether there is one.
+    LimitReached(Option<(i64, HashSet<String>)>),
+    Infeasible,
+}
+
+// A constraint normalized as sum(coefficient * variable) <= rhs
+struct Row {
+    terms: Vec<(i64, usize)>,
+    rhs: i64,
+}
+
+// Immutable description of the problem, variables are referred to by index
+struct Model {
+    objective: Vec<i64>,
+    rows: Vec<Row>,
+    // For every variable, the rows it appears in with its coefficient
+    var_rows: Vec<Vec<(usize, i64)>>,
+    // Sets of variables of which at most one can be 1 (one per desired
+    // meeting for example), sorted by decreasing objective. We branch on
+    // which variable of the group is picked.
+    groups: Vec<Vec<usize>>,
+    // Whether exactly one variable of the group has to be picked
+    mandatory: Vec<bool>,
+    // Variables that are not part of any group
+    free_vars: Vec<usize>,
+}
+
+struct State {
+    value: Vec<Option<bool>>,
+    // For every row, the smallest value the left hand side can still take
+    // given the variables fixed so far
+    min_activity: Vec<i64>,
+    trail: Vec<usize>,
+    current: i64,
+    best: Option<(i64, Vec<bool>)>,
+    nodes: usize,
+    node_limit: usize,
+}
+
+fn is_group(terms: &[(i64, String)], rhs: i64) -> bool {
+    rhs == 1 && terms.len() > 1 && terms.iter().all(|k| k.0 == 1)
+}
+
+impl Model {
+    fn new(p: &Problem) -> Model {
+        let index: HashMap<&str, usize> = p.variables
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.as_str(), i))
+            .collect();
+        let n = p.variables.len();
+
+        let mut objective = vec![0; n];
+        for &(coef, ref var) in &p.objective {
+            objective[index[var.as_str()]] += coef;
+        }
+
+        let mut rows: Vec<Row> = Vec::new();
+        let mut group_rows: Vec<(bool, Vec<usize>)> = Vec::new();
+        for c in &p.constraints {
+            let terms = c.terms
+                .iter()
+                .map(|k| (k.0, index[k.1.as_str()]))
+                .collect::<Vec<(i64, usize)>>();
+            if is_group(&c.terms, c.rhs) {
+                group_rows.push((
+                    c.relation == Relation::Eq,
+                    terms.iter().map(|k| k.1).collect(),
+                ));
+            }
+            if c.relation == Relation::Eq {
+                rows.push(Row {
+                    terms: terms.iter().map(|k| (-k.0, k.1)).collect(),
+                    rhs: -c.rhs,
+                });
+            }
+            rows.push(Row { terms, rhs: c.rhs });
+        }
+
+        let mut var_rows = vec![Vec::new(); n];
+        for (r, row) in rows.iter().enumerate() {
+            for &(coef, v) in &row.terms {
+                var_rows[v].push((r, coef));
+            }
+        }
+
+        // Every variable belongs to at most one group, larger and mandatory
+        // groups win over small ones like pairs of conflicting candidates
+        group_rows.sort_by_key(|k| Reverse((k.0, k.1.len())));
+        let mut in_group: HashSet<usize> = HashSet::new();
+        let mut groups = Vec::new();
+        let mut mandatory = Vec::new();
+        for (is_mandatory, vars) in group_rows {
+            let mut vars = vars.into_iter()
+                .filter(|v| !in_group.contains(v))
+                .collect::<Vec<usize>>();
+            if vars.is_empty() {
+                continue;
+            }
+            vars.sort_by(|a, b| objective[*b].cmp(&objective[*a]));
+            in_group.extend(vars.iter().cloned());
+            groups.push(vars);
+            mandatory.push(is_mandatory);
+        }
+        let mut free_vars = (0..n)
+            .filter(|v| !in_group.contains(v))
+            .collect::<Vec<usize>>();
+        free_vars.sort_by(|a, b| objective[*b].cmp(&objective[*a]));
+
+        Model {
+            objective,
+            rows,
+            var_rows,
+            groups,
+            mandatory,
+            free_vars,
+        }
+    }
+}
+
+impl State {
+    fn new(m: &Model, node_limit: usize) -> State {
+        State {
+            value: vec![None; m.objective.len()],
+            min_activity: m.rows
+                .iter()
+                .map(|r| r.terms.iter().filter(|k| k.0 < 0).map(|k| k.0).sum())
+                .collect(),
+            trail: Vec::new(),
+            current: 0,
+            best: None,
+            nodes: 0,
+            node_limit,
+        }
+    }
+
+    // Fix a variable and propagate the consequences on the other variables,
+    // returns false if a constraint cannot be satisfied anymore. On failure
+    // the caller is responsible for undoing the partial assignment.
+    fn assign(&mut self, m: &Model, var: usize, val: bool) -> bool {
+        let mut queue = vec![(var, val)];
+        while let Some((v, val)) = queue.pop() {
+            if let Some(k) = self.value[v] {
+                if k != val {
+                    return false;
+                }
+                continue;
+            }
+            self.value[v] = Some(val);
+            self.trail.push(v);
+            if val {
+                self.current += m.objective[v];
+            }
+            for &(r, coef) in &m.var_rows[v] {
+                if val && coef > 0 {
+                    self.min_activity[r] += coef;
+                } else if !val && coef < 0 {
+                    self.min_activity[r] -= coef;
+                }
+            }
+            for &(r, _) in &m.var_rows[v] {
+                let slack = m.rows[r].rhs - self.min_activity[r];
+                if slack < 0 {
+                    return false;
+                }
+                for &(coef, u) in &m.rows[r].terms {
+                    if self.value[u].is_some() {
+                        continue;
+                    }
+                    if coef > slack {
+                        queue.push((u, false));
+                    } else if -coef > slack {
+                        queue.push((u, true));
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn undo(&mut self, m: &Model, len: usize) {
+        while self.trail.len() > len {
+            let v = self.trail.pop().expect("Empty trail");
+            let val = self.value[v].take().expect("Unassigned variable on the trail");
+            if val {
+                self.current -= m.objective[v];
+            }
+            for &(r, coef) in &m.var_rows[v] {
+                if val && coef > 0 {
+                    self.min_activity[r] -= coef;
+                } else if !val && coef < 0 {
+                    self.min_activity[r] += coef;
+                }
+            }
+        }
+    }
+
+    // Best objective reachable from the current node
+    fn upper_bound(&self, m: &Model) -> i64 {
+        let mut bound = self.current;
+        for g in &m.groups {
+            if g.iter().any(|v| self.value[*v] == Some(true)) {
+                continue;
+            }
+            bound += g.iter()
+                .filter(|v| self.value[**v].is_none())
+                .map(|v| m.objective[*v])
+                .max()
+                .unwrap_or(0)
+                .max(0);
+        }
+        for v in &m.free_vars {
+            if self.value[*v].is_none() {
+                bound += m.objective[*v].max(0);
+            }
+        }
+        bound
+    }
+
+    // Try a branch: apply the assignments, explore and backtrack
+    fn branch(&mut self, m: &Model, assignments: &[(usize, bool)]) {
+        let len = self.trail.len();
+        if assignments.iter().all(|&(v, val)| self.assign(m, v, val)) {
+            self.search(m);
+        }
+        self.undo(m, len);
+    }
+
+    fn search(&mut self, m: &Model) {
+        self.nodes += 1;
+        if self.nodes > self.node_limit {
+            return;
+        }
+        if let Some((best, _)) = self.best {
+            if self.upper_bound(m) <= best {
+                return;
+            }
+        }
+
+        // Branch on the open group with the fewest options left, mandatory
+        // groups first since they are the most constrained
+        let open_group = m.groups
+            .iter()
+            .enumerate()
+            .filter(|&(_, g)| {
+                g.iter().all(|v| self.value[*v] != Some(true))
+                    && g.iter().any(|v| self.value[*v].is_none())
+            })
+            .min_by_key(|&(i, g)| {
+                (
+                    !m.mandatory[i],
+                    g.iter().filter(|v| self.value[**v].is_none()).count(),
+                )
+            });
+        if let Some((_, g)) = open_group {
+            let open = g.iter()
+                .cloned()
+                .filter(|v| self.value[*v].is_none())
+                .collect::<Vec<usize>>();
+            for v in &open {
+                self.branch(m, &[(*v, true)]);
+            }
+            self.branch(
+                m,
+                &open.iter().map(|v| (*v, false)).collect::<Vec<(usize, bool)>>(),
+            );
+            return;
+        }
+
+        if let Some(v) = m.free_vars.iter().find(|v| self.value[**v].is_none()) {
+            let preferred = m.objective[*v] > 0;
+            self.branch(m, &[(*v, preferred)]);
+            self.branch(m, &[(*v, !preferred)]);
+            return;
+        }
+
+        // Every variable is fixed and propagation guarantees that all the
+        // constraints are satisfied
+        if self.best.as_ref().is_none_or(|b| self.current > b.0) {
+            let values = self.value.iter().map(|k| *k == Some(true)).collect();
+            self.best = Some((self.current, values));
+        }
+    }
+}
+
+pub fn solve(p: &Problem) -> Outcome {
+    solve_with_node_limit(p, NODE_LIMIT)
+}
+
+pub fn solve_with_node_limit(p: &Problem, node_limit: usize) -> Outcome {
+    let model = Model::new(p);
+    let mut state = State::new(&model, node_limit);
+    // Rows without any variable can be violated from the start
+    if state
+        .min_activity
+        .iter()
+        .zip(model.rows.iter())
+        .any(|(a, r)| *a > r.rhs)
+    {
+        return Outcome::Infeasible;
+    }
+    state.search(&model);
+    let best = state.best.map(|(score, values)| {
+        let chosen = p.variables
+            .iter()
+            .zip(values)
+            .filter(|k| k.1)
+            .map(|k| k.0.to_string())
+            .collect();
+        (score, chosen)
+    });
+    match best {
+        _ if state.nodes > node_limit => Outcome::LimitReached(best),
+        Some((score, chosen)) => Outcome::Optimal(score, chosen),
+        None => Outcome::Infeasible,
+    }
+}
+
+#[cfg(test)]
+fn test_problem(objective: &[(i64, &str)], groups: &[&[&str]], pairs: &[[&str; 2]]) -> Problem {
+    use solver::Constraint;
+    let to_terms = |vars: &[&str]| vars.iter().map(|k| (1, k.to_string())).collect();
+    let mut constraints = groups
+        .iter()
+        .map(|g| Constraint {
+            terms: to_terms(g),
+            relation: Relation::Eq,
+            rhs: 1,
+        })
+        .collect::<Vec<Constraint>>();
+    constraints.extend(pairs.iter().map(|p| Constraint {
+        terms: to_terms(p),
+        relation: Relation::Le,
+        rhs: 1,
+    }));
+    Problem {
+        objective: objective.iter().map(|k| (k.0, k.1.to_string())).collect(),
+        constraints,
+        variables: objective.iter().map(|k| k.1.to_string()).collect(),
+    }
+}
+
+#[test]
+fn test_solve_finds_optimum_over_greedy() {
+    // Greedily picking a1 (best score) forces b2 and c2, the optimum is to
+    // give up on a1 to get b1 and c1
+    let p = test_problem(
+        &[(10, "a1"), (5, "a2"), (8, "b1"), (1, "b2"), (8, "c1"), (1, "c2")],
+        &[&["a1", "a2"], &["b1", "b2"], &["c1", "c2"]],
+        &[["a1", "b1"], ["a1", "c1"]],
+    );
+    let expected: HashSet<String> = ["a2", "b1", "c1"].iter().map(|k| k.to_string()).collect();
+    assert_eq!(solve(&p), Outcome::Optimal(21, expected));
+}
+
+#[test]
+fn test_solve_infeasible() {
+    let p = test_problem(
+        &[(1, "a1"), (1, "b1")],
+        &[&["a1"], &["b1"]],
+        &[["a1", "b1"]],
+    );
+    assert_eq!(solve(&p), Outcome::Infeasible);
+}
+
+#[test]
+fn test_solve_node_limit_is_not_infeasible() {
+    let p = test_problem(
+        &[(10, "a1"), (5, "a2"), (8, "b1"), (1, "b2"), (8, "c1"), (1, "c2")],
+        &[&["a1", "a2"], &["b1", "b2"], &["c1", "c2"]],
+        &[["a1", "b1"], ["a1", "c1"]],
+    );
+    assert_eq!(solve_with_node_limit(&p, 1), Outcome::LimitReached(None));
+    match solve_with_node_limit(&p, 4) {
+        Outcome::LimitReached(Some(_)) => (),
+        k => panic!("Expected the greedy solution, got {:?}", k),
+    }
+}
@@ -0,0 +1,460 @@
+// CalDAV (RFC 4791) calendars, as served by Nextcloud or Radicale. The
+// calendar of every person is found by replacing {email} in a url template
+// and the meetings are booked in one calendar.
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::ops::Range;
+use bio::data_structures::interval_tree::IntervalTree;
+use chrono;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use hyper;
+use hyper::header::{Authorization, Basic, Headers};
+use hyper::method::Method;
+use hyper::net::HttpsConnector;
+use hyper_rustls;
+use backend::{CalendarBackend, NewEvent};
+use fixtures;
+use ical;
+use types::{CalDavConfig, CalendarSettings, MeetingsTree, Room};
+
+fn calendar_query(window: &Range<DateTime<chrono::Utc>>) -> String {
+    let (start, end) = (ical::format_utc(&window.start), ical::format_utc(&window.end));
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop>
+    <C:calendar-data>
+      <C:expand start="{start}" end="{end}"/>
+    </C:calendar-data>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{start}" end="{end}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#,
+        start = start,
+        end = end
+    )
+}
+
+fn free_busy_query(window: &Range<DateTime<chrono::Utc>>) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<C:free-busy-query xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <C:time-range start="{}" end="{}"/>
+</C:free-busy-query>"#,
+        ical::format_utc(&window.start),
+        ical::format_utc(&window.end)
+    )
+}
+
+const TIMEZONE_QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propfind xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop>
+    <C:calendar-timezone/>
+  </D:prop>
+</D:propfind>"#;
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&amp;", "&")
+}
+
+// The text of the elements with this local name, whatever their namespace
+// prefix, nothing when a tag is not closed. CDATA sections are not supported.
+fn xml_texts(xml: &str, name: &str) -> Vec<String> {
+    let local_name = |tag: &str| -> String {
+        let tag = tag.split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or_default();
+        tag.rsplit(':').next().unwrap_or_default().to_string()
+    };
+    let mut res = Vec::new();
+    let mut rest = xml;
+    while let Some(k) = rest.find('<') {
+        rest = &rest[k + 1..];
+        if rest.starts_with('/') || local_name(rest) != name {
+            continue;
+        }
+        let end_of_tag = match rest.find('>') {
+            Some(k) => k,
+            None => {
+                eprintln!("WARNING: malformed XML answer, ignored");
+                return Vec::new();
+            }
+        };
+        if rest[..end_of_tag].ends_with('/') {
+            res.push(String::new());
+            rest = &rest[end_of_tag + 1..];
+            continue;
+        }
+        rest = &rest[end_of_tag + 1..];
+        // Up to the matching closing tag
+        let mut text_end = 0;
+        while let Some(j) = rest[text_end..].find("</") {
+            let close = text_end + j;
+            if local_name(&rest[close + 2..]) == name {
+                res.push(unescape_xml(&rest[..close]));
+                break;
+            }
+            text_end = close + 2;
+        }
+        rest = &rest[text_end..];
+    }
+    res
+}
+
+// Floating times and dates are in the timezone of the calendar
+fn events_to_tree(
+    calendars: &[String],
+    person: &str,
+    tz: &Tz,
+    ignore_all_day_events: bool,
+    ignore_meetings_with_no_response: bool,
+) -> MeetingsTree {
+    let mut tree = IntervalTree::new();
+    for c in calendars {
+        for event in ical::parse(c).find_all("VEVENT") {
+            let blocks = ical::blocks(
+                event,
+                person,
+                ignore_all_day_events,
+                ignore_meetings_with_no_response,
+            );
+            if !blocks {
+                continue;
+            }
+            let (start, end) = event.event_bounds(tz);
+            let id = event.property("UID").map(|k| k.value.to_string()).unwrap_or_default();
+            tree.insert(start..end, id);
+        }
+    }
+    tree
+}
+
+// The busy periods of a VFREEBUSY, given as start/end or start/duration
+fn free_busy_to_tree(calendar: &str) -> MeetingsTree {
+    let mut tree = IntervalTree::new();
+    for fb in ical::parse(calendar).find_all("VFREEBUSY") {
+        for p in fb.properties_named("FREEBUSY") {
+            if p.param("FBTYPE") == Some("FREE") {
+                continue;
+            }
+            for (k, period) in p.value.split(',').enumerate() {
+                let mut bounds = period.splitn(2, '/');
+                let start = ical::parse_datetime(bounds.next().unwrap_or_default(), &Tz::UTC);
+                let end = match bounds.next() {
+                    Some(k) if k.starts_with('P') => start + ical::parse_duration(k),
+                    Some(k) => ical::parse_datetime(k, &Tz::UTC),
+                    None => panic!("Malformed free busy period {}", period),
+                };
+                tree.insert(start..end, format!("busy_{}", k));
+            }
+        }
+    }
+    tree
+}
+
+pub struct CalDav {
+    pub config: CalDavConfig,
+    // Only read the busy times of the calendars, with free-busy-query
+    pub freebusy: bool,
+}
+
+impl CalDav {
+    fn calendar_url(&self, person: &str) -> String {
+        self.config.calendar_url.replace("{email}", person)
+    }
+
+    fn event_url(&self, uid: &str) -> String {
+        format!("{}/{}.ics", self.config.booking_url.trim_end_matches('/'), uid)
+    }
+
+    fn request(&self, method: Method, url: &str, mut headers: Headers, body: &str) -> String {
+        let client =
+            hyper::Client::with_connector(HttpsConnector::new(hyper_rustls::TlsClient::new()));
+        if let Some(ref username) = self.config.username {
+            headers.set(Authorization(Basic {
+                username: username.to_string(),
+                password: self.config.password.clone(),
+            }));
+        }
+        let mut response = client
+            .request(method.clone(), url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .expect("Cannot reach the CalDAV server");
+        if !response.status.is_success() {
+            panic!("The CalDAV server refused {} {}: {}", method, url, response.status);
+        }
+        let mut res = String::new();
+        response
+            .read_to_string(&mut res)
+            .expect("Cannot read the CalDAV answer");
+        res
+    }
+
+    fn report(&self, url: &str, body: &str) -> String {
+        let mut headers = Headers::new();
+        headers.set_raw("Depth", vec![b"1".to_vec()]);
+        headers.set_raw("Content-Type", vec![b"application/xml; charset=utf-8".to_vec()]);
+        self.request(Method::Extension("REPORT".to_string()), url, headers, body)
+    }
+
+    // From the VTIMEZONE in the calendar-timezone of the calendar
+    fn calendar_timezone(&self, person: &str) -> Option<Tz> {
+        let mut headers = Headers::new();
+        headers.set_raw("Depth", vec![b"0".to_vec()]);
+        headers.set_raw("Content-Type", vec![b"application/xml; charset=utf-8".to_vec()]);
+        let answer = self.request(
+            Method::Extension("PROPFIND".to_string()),
+            &self.calendar_url(person),
+            headers,
+            TIMEZONE_QUERY,
+        );
+        xml_texts(&answer, "calendar-timezone")
+            .iter()
+            .filter_map(|k| {
+                ical::parse(k)
+                    .find_all("VTIMEZONE")
+                    .into_iter()
+                    .filter_map(ical::vtimezone_timezone)
+                    .next()
+            })
+            .next()
+    }
+
+    fn fetch_one(
+        &self,
+        person: &str,
+        window: &Range<DateTime<chrono::Utc>>,
+        ignore_all_day_events: bool,
+        ignore_meetings_with_no_response: bool,
+    ) -> MeetingsTree {
+        println!("Fetching for {:?}", person);
+        let url = self.calendar_url(person);
+        if self.freebusy {
+            return free_busy_to_tree(&self.report(&url, &free_busy_query(window)));
+        }
+        let tz = self.calendar_timezone(person).unwrap_or(Tz::UTC);
+        let answer = self.report(&url, &calendar_query(window));
+        events_to_tree(
+            &xml_texts(&answer, "calendar-data"),
+            person,
+            &tz,
+            ignore_all_day_events,
+            ignore_meetings_with_no_response,
+        )
+    }
+
+    fn put(&self, uid: &str, event: &NewEvent, headers: Headers) {
+        let body = ical::calendar(
+            &[(uid.to_string(), event)],
+            &self.config.organizer,
+            &chrono::Utc::now(),
+        );
+        let mut headers = headers;
+        headers.set_raw("Content-Type", vec![b"text/calendar; charset=utf-8".to_vec()]);
+        self.request(Method::Put, &self.event_url(uid), headers, &body);
+    }
+}
+
+impl CalendarBackend for CalDav {
+    fn fetch_availability(
+        &self,
+        people: Vec<String>,
+        window: &Range<DateTime<chrono::Utc>>,
+        ignore_all_day_events: bool,
+        ignore_meetings_with_no_response: bool,
+    ) -> HashMap<String, MeetingsTree> {
+        people
+            .into_iter()
+            .map(|p| {
+                let tree = self.fetch_one(
+                    &p,
+                    window,
+                    ignore_all_day_events,
+                    ignore_meetings_with_no_response,
+                );
+                (p, tree)
+            })
+            .collect()
+    }
+
+    fn fetch_settings(
+        &self,
+        people: Vec<String>,
+        _: &Range<DateTime<chrono::Utc>>,
+    ) -> HashMap<String, CalendarSettings> {
+        people
+            .into_iter()
+            .filter_map(|p| {
+                let timezone = self.calendar_timezone(&p)?;
+                Some((
+                    p,
+                    CalendarSettings {
+                        timezone,
+                        working_hours: None,
+                        working_days: Vec::new(),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn create_events(&self, events: &[NewEvent]) -> Vec<String> {
+        let now = chrono::Utc::now();
+        events
+            .iter()
+            .enumerate()
+            .map(|(k, e)| {
+                let uid = ical::new_uid(&now, k);
+                // Never overwrite an existing event
+                let mut headers = Headers::new();
+                headers.set_raw("If-None-Match", vec![b"*".to_vec()]);
+                self.put(&uid, e, headers);
+                uid
+            })
+            .collect()
+    }
+
+    fn update_event(&self, id: &str, event: &NewEvent) {
+        self.put(id, event, Headers::new());
+    }
+
+    fn delete_event(&self, id: &str) {
+        self.request(Method::Delete, &self.event_url(id), Headers::new(), "");
+    }
+
+    // CalDAV has no directory of rooms, they come from the config
+    fn list_rooms(&self) -> Vec<Room> {
+        Vec::new()
+    }
+}
+
+#[test]
+fn test_xml_texts() {
+    let xml = "<d:response><c:calendar-data>a &amp; b</c:calendar-data><calendar-data/>";
+    assert_eq!(xml_texts(xml, "calendar-data"), vec!["a & b".to_string(), String::new()]);
+    assert!(xml_texts("<d:response><c:calendar-data", "calendar-data").is_empty());
+}
+
+#[cfg(test)]
+fn test_backend(url: &str, freebusy: bool) -> CalDav {
+    CalDav {
+        config: CalDavConfig {
+            calendar_url: format!("{}{{email}}/calendar/", url),
+            booking_url: format!("{}me@foo.com/calendar", url),
+            organizer: "me@foo.com".to_string(),
+            username: Some("me".to_string()),
+            password: Some("secret".to_string()),
+        },
+        freebusy,
+    }
+}
+
+#[test]
+fn test_caldav_fetch_events() {
+    let (url, server) =
+        fixtures::serve_api(vec![fixtures::caldav_timezone(), fixtures::caldav_events()]);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let window = at("2018-02-08T00:00:00Z")..at("2018-02-15T00:00:00Z");
+    let avail = test_backend(&url, false).fetch_availability(
+        vec!["laurent.charignon@foo.com".to_string()],
+        &window,
+        true,
+        true,
+    );
+    // The declined, transparent and all day events do not count
+    let mut ids = avail["laurent.charignon@foo.com"]
+        .find(window.clone())
+        .map(|k| k.data().to_string())
+        .collect::<Vec<String>>();
+    ids.sort();
+    assert_eq!(ids, vec!["accepted", "floating", "organized"]);
+    let interval = avail["laurent.charignon@foo.com"]
+        .find(window.clone())
+        .find(|k| k.data() == "accepted")
+        .map(|k| k.interval().start..k.interval().end)
+        .unwrap();
+    assert_eq!(interval, at("2018-02-08T09:00:00-08:00")..at("2018-02-08T10:00:00-08:00"));
+    // Floating times are in the timezone of the calendar
+    let interval = avail["laurent.charignon@foo.com"]
+        .find(window.clone())
+        .find(|k| k.data() == "floating")
+        .map(|k| k.interval().start..k.interval().end)
+        .unwrap();
+    assert_eq!(interval, at("2018-02-13T09:00:00Z")..at("2018-02-13T10:00:00Z"));
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("PROPFIND /laurent.charignon@foo.com/calendar/ HTTP/1.1"));
+    assert!(requests[1].starts_with("REPORT /laurent.charignon@foo.com/calendar/ HTTP/1.1"));
+    let range = r#"<C:time-range start="20180208T000000Z" end="20180215T000000Z"/>"#;
+    assert!(requests[1].contains(range));
+}
+
+#[test]
+fn test_caldav_free_busy() {
+    let (url, server) = fixtures::serve_api(vec![fixtures::caldav_free_busy()]);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let window = at("2018-02-08T00:00:00Z")..at("2018-02-15T00:00:00Z");
+    let avail = test_backend(&url, true).fetch_availability(
+        vec!["laurent.charignon@foo.com".to_string()],
+        &window,
+        true,
+        true,
+    );
+    let mut busy = avail["laurent.charignon@foo.com"]
+        .find(window.clone())
+        .map(|k| k.interval().start..k.interval().end)
+        .collect::<Vec<Range<DateTime<chrono::Utc>>>>();
+    busy.sort_by_key(|k| k.start);
+    assert_eq!(
+        busy,
+        vec![
+            at("2018-02-08T17:00:00Z")..at("2018-02-08T18:00:00Z"),
+            at("2018-02-09T17:00:00Z")..at("2018-02-09T17:30:00Z"),
+        ]
+    );
+    assert!(server.join().unwrap()[0].contains("<C:free-busy-query"));
+}
+
+#[test]
+fn test_caldav_create_events() {
+    let (url, server) = fixtures::serve_api(vec![String::new()]);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let event = NewEvent {
+        title: "title".to_string(),
+        description: "description".to_string(),
+        attendees: vec!["laurent.charignon@foo.com".to_string()],
+        optional_attendees: Vec::new(),
+        room: None,
+        start: at("2018-02-08T09:00:00-08:00"),
+        end: at("2018-02-08T10:00:00-08:00"),
+        timezone: "America/Los_Angeles".parse().unwrap(),
+        recurrence: None,
+    };
+    let ids = test_backend(&url, false).create_events(&[event]);
+    assert_eq!(ids.len(), 1);
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with(&format!("PUT /me@foo.com/calendar/{}.ics HTTP/1.1", ids[0])));
+    assert!(requests[0].contains("If-None-Match: *"));
+    assert!(requests[0].contains("Authorization: Basic"));
+    let body = &requests[0][requests[0].find("\r\n\r\n").unwrap()..];
+    let root = ical::parse(body);
+    let events = root.find_all("VEVENT");
+    assert_eq!(events[0].property("UID").unwrap().value, ids[0]);
+    assert_eq!(events[0].property("DTSTART").unwrap().value, "20180208T170000Z");
+    assert_eq!(events[0].property("ATTENDEE").unwrap().value, "mailto:laurent.charignon@foo.com");
+    assert_eq!(events[0].property("ORGANIZER").unwrap().value, "mailto:me@foo.com");
+}
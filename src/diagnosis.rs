@@ -0,0 +1,263 @@
+// Explain why a problem has no solution: which meetings have no possible slot
+// at all, and which groups of meetings cannot be scheduled together.
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use bnb;
+use fixtures;
+use solver::SolverInput;
+use types::CandidateStats;
+
+// Stop looking for more sets of meetings blocking each other past this
+const MAX_BLOCKING_SETS: usize = 5;
+// Nodes explored by every check before giving up. The deletion filter runs
+// one check per meeting, a much smaller budget than the solve keeps the
+// diagnosis from taking far longer than the solve itself.
+const NODE_LIMIT: usize = 50_000;
+
+// A minimal set of meetings that cannot all be scheduled, removing any of
+// them makes the others possible
+#[derive(Debug, PartialEq)]
+pub struct BlockingSet {
+    pub titles: Vec<String>,
+    // Attendees invited to more than one of the meetings, freeing their time
+    // is the most likely way to unblock the set
+    pub shared_attendees: Vec<String>,
+    // Interview loops of the set with session orders left out, which could
+    // have fit
+    pub capped_loops: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Diagnosis {
+    pub no_candidates: Vec<(String, CandidateStats)>,
+    pub blocking_sets: Vec<BlockingSet>,
+    // The solver gave up on some of the checks, the sets may not be minimal
+    // and there may be more of them
+    pub incomplete: bool,
+}
+
+// None when the solver gave up before knowing
+fn is_feasible(s: &SolverInput, titles: &HashSet<String>) -> Option<bool> {
+    let mut restricted = s.restricted_to(titles);
+    restricted.best_effort = false;
+    let mut problem = restricted.to_problem();
+    // Any solution will do, stop at the first one
+    problem.objective.clear();
+    match bnb::solve_with_node_limit(&problem, NODE_LIMIT) {
+        bnb::Outcome::Optimal(_, _) | bnb::Outcome::LimitReached(Some(_)) => Some(true),
+        bnb::Outcome::LimitReached(None) => None,
+        bnb::Outcome::Infeasible => Some(false),
+    }
+}
+
+// Deletion filter: drop meetings one by one as long as the remaining ones
+// still cannot be scheduled together. Meetings are kept when we cannot tell,
+// the second value is whether the set is known to be minimal.
+fn minimal_blocking_set(s: &SolverInput, titles: &[String]) -> (Vec<String>, bool) {
+    let mut set = titles.to_vec();
+    let mut minimal = true;
+    for t in titles {
+        let without = set.iter()
+            .filter(|k| *k != t)
+            .cloned()
+            .collect::<HashSet<String>>();
+        match is_feasible(s, &without) {
+            Some(false) => set.retain(|k| k != t),
+            Some(true) => (),
+            None => minimal = false,
+        }
+    }
+    (set, minimal)
+}
+
+fn shared_attendees(s: &SolverInput, titles: &[String]) -> Vec<String> {
+    let mut count: HashMap<&str, usize> = HashMap::new();
+    for m in &s.desired_meetings {
+        if titles.contains(&m.title) {
+            let interviewers = m.sessions.iter().flat_map(|k| k.interviewers.iter());
+            for a in m.attendees.iter().chain(interviewers) {
+                *count.entry(a.as_str()).or_insert(0) += 1;
+            }
+        }
+    }
+    let mut res = count
+        .into_iter()
+        .filter(|k| k.1 > 1)
+        .map(|k| k.0.to_string())
+        .collect::<Vec<String>>();
+    res.sort();
+    res
+}
+
+impl Diagnosis {
+    pub fn new(s: &SolverInput) -> Diagnosis {
+        let mut no_candidates = Vec::new();
+        let mut pool = Vec::new();
+        for m in &s.desired_meetings {
+            if s.candidate_per_desired_meeting.contains_key(&m.title) {
+                pool.push(m.title.to_string());
+            } else {
+                let stats = s.stats.get(&m.title).cloned().unwrap_or_default();
+                no_candidates.push((m.title.to_string(), stats));
+            }
+        }
+        pool.sort();
+
+        // Look for several sets by removing, after each one found, its
+        // meeting with the fewest candidates
+        let mut blocking_sets = Vec::new();
+        let mut incomplete = false;
+        while blocking_sets.len() < MAX_BLOCKING_SETS {
+            match is_feasible(s, &pool.iter().cloned().collect()) {
+                Some(true) => break,
+                Some(false) => (),
+                None => {
+                    incomplete = true;
+                    break;
+                }
+            }
+            let (titles, minimal) = minimal_blocking_set(s, &pool);
+            incomplete |= !minimal;
+            let most_constrained = titles
+                .iter()
+                .min_by_key(|k| s.candidate_per_desired_meeting[*k].len())
+                .cloned()
+                .expect("A blocking set cannot be empty");
+            pool.retain(|k| *k != most_constrained);
+            let capped_loops = titles
+                .iter()
+                .filter(|k| s.stats.get(*k).is_some_and(|k| k.capped_session_orders > 0))
+                .cloned()
+                .collect();
+            blocking_sets.push(BlockingSet {
+                shared_attendees: shared_attendees(s, &titles),
+                capped_loops,
+                titles,
+            });
+        }
+
+        Diagnosis {
+            no_candidates,
+            blocking_sets,
+            incomplete,
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (title, stats) in &self.no_candidates {
+            writeln!(f, "{:?} has no possible slot: {}", title, stats)?;
+            let mut busy = stats
+                .conflicts_per_attendee
+                .iter()
+                .collect::<Vec<(&String, &usize)>>();
+            busy.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            for (attendee, count) in busy {
+                writeln!(f, "    {} is busy for {} of them", attendee, count)?;
+            }
+        }
+        for set in &self.blocking_sets {
+            writeln!(
+                f,
+                "These meetings cannot all be scheduled together: {}",
+                set.titles
+                    .iter()
+                    .map(|k| format!("{:?}", k))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )?;
+            if !set.shared_attendees.is_empty() {
+                writeln!(
+                    f,
+                    "  ask these attendees to free some time: {}",
+                    set.shared_attendees.join(", ")
+                )?;
+            }
+            if !set.capped_loops.is_empty() {
+                writeln!(
+                    f,
+                    "  only some orders of the sessions of {} were tried",
+                    set.capped_loops.join(", ")
+                )?;
+            }
+        }
+        if self.incomplete {
+            writeln!(
+                f,
+                "The builtin solver gave up on some checks, there may be other blocking meetings"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_diagnosis_finds_blocking_meetings() {
+    let input = fixtures::conflicting_solver_input();
+    let diagnosis = Diagnosis::new(&input);
+    assert_eq!(diagnosis.no_candidates, vec![]);
+    assert!(!diagnosis.incomplete);
+    assert_eq!(
+        diagnosis.blocking_sets,
+        vec![
+            BlockingSet {
+                titles: vec!["title".to_string(), "title2".to_string()],
+                shared_attendees: vec!["laurent.charignon@foo.com".to_string()],
+                capped_loops: vec![],
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_diagnosis_of_meetings_too_far_apart() {
+    // Both meetings are between 10 and 12, the review cannot start two hours
+    // after the end of the kickoff
+    let input = fixtures::solver_input(
+        &fixtures::test_dependent_input().replace("min_gap: 60", "min_gap: 120"),
+        &fixtures::test_options(),
+    );
+    let diagnosis = Diagnosis::new(&input);
+    assert_eq!(diagnosis.no_candidates, vec![]);
+    assert_eq!(
+        diagnosis.blocking_sets,
+        vec![
+            BlockingSet {
+                titles: vec!["kickoff".to_string(), "review".to_string()],
+                shared_attendees: vec![],
+                capped_loops: vec![],
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_diagnosis_reports_meetings_without_candidates() {
+    let mut input = fixtures::conflicting_solver_input();
+    input.candidate_per_desired_meeting.remove("title2");
+    let stats = CandidateStats {
+        rejected_by_conflict: 3,
+        ..Default::default()
+    };
+    input.stats.insert("title2".to_string(), stats.clone());
+
+    let diagnosis = Diagnosis::new(&input);
+    assert_eq!(diagnosis.no_candidates, vec![("title2".to_string(), stats)]);
+    assert_eq!(diagnosis.blocking_sets, vec![]);
+}
+
+#[test]
+fn test_diagnosis_reports_capped_session_orders() {
+    let mut input = fixtures::conflicting_solver_input();
+    let stats = CandidateStats {
+        capped_session_orders: 2,
+        ..Default::default()
+    };
+    input.stats.insert("title".to_string(), stats);
+
+    let diagnosis = Diagnosis::new(&input);
+    assert_eq!(diagnosis.blocking_sets[0].capped_loops, vec!["title".to_string()]);
+    assert!(diagnosis.to_string().contains("only some orders of the sessions of title"));
+}
@@ -0,0 +1,107 @@
+// Share the early and late slots fairly between time zones. A candidate is
+// inconvenient for a time zone if it is at the very start or end of the
+// working hours of one of its attendees living there. Every inconvenient
+// candidate picked for a zone sets one more of its overload variables, each
+// costing more than the previous one, so that the solver prefers to spread
+// the burden over the zones rather than always asking the same one:
+// sum(inconvenient candidates) - sum(overload variables) <= 0
+use std::collections::HashMap;
+use std::collections::HashSet;
+use fixtures;
+use solver::{Constraint, Relation};
+use types::{DesiredMeeting, MeetingCandidate, Preferences};
+
+// Cost of the first inconvenient meeting of a zone, the n-th costs n times
+// more
+const FAIRNESS_PENALTY: i64 = 100;
+
+// Returns the cost of the overload variables and the constraints. Only the
+// attendees with a timezone in their preferences are considered, and nothing
+// is done unless they live in at least two zones.
+pub fn fairness_constraints(
+    desired_meetings: &[DesiredMeeting],
+    candidates: &HashMap<String, MeetingCandidate>,
+    preferences: &HashMap<String, Preferences>,
+) -> (Vec<(i64, String)>, Vec<Constraint>) {
+    let mut zones = preferences
+        .values()
+        .filter_map(|p| p.timezone.clone())
+        .collect::<Vec<String>>();
+    zones.sort();
+    zones.dedup();
+    if zones.len() < 2 {
+        return (Vec::new(), Vec::new());
+    }
+
+    let timezones = desired_meetings
+        .iter()
+        .map(|m| (m.title.as_str(), m.timezone))
+        .collect::<HashMap<_, _>>();
+    let mut inconvenient: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (id, c) in candidates {
+        let tz = timezones[c.title.as_str()];
+        let zones_of_candidate = c.reservations()
+            .into_iter()
+            .filter_map(|(who, range)| {
+                let p = preferences.get(&who)?;
+                let zone = p.timezone.as_ref()?;
+                if p.is_inconvenient(&tz, range.start, range.end) {
+                    Some(zone.as_str())
+                } else {
+                    None
+                }
+            })
+            .collect::<HashSet<&str>>();
+        for zone in zones_of_candidate {
+            inconvenient.entry(zone).or_default().push(id.as_str());
+        }
+    }
+
+    let mut penalties = Vec::new();
+    let mut constraints = Vec::new();
+    for (n, zone) in zones.iter().enumerate() {
+        let mut ids = match inconvenient.get(zone.as_str()) {
+            Some(ids) => ids.clone(),
+            None => continue,
+        };
+        ids.sort();
+        // At most one candidate per meeting is picked
+        let meetings = ids.iter()
+            .map(|k| candidates[*k].title.as_str())
+            .collect::<HashSet<&str>>()
+            .len();
+        let mut terms = ids.iter()
+            .map(|k| (1, k.to_string()))
+            .collect::<Vec<(i64, String)>>();
+        for j in 0..meetings {
+            let var = format!("unfair_{}_{}", n, j);
+            terms.push((-1, var.clone()));
+            penalties.push((-FAIRNESS_PENALTY * (j as i64 + 1), var));
+        }
+        constraints.push(Constraint {
+            terms,
+            relation: Relation::Le,
+            rhs: 0,
+        });
+    }
+    (penalties, constraints)
+}
+
+#[test]
+fn test_fairness_between_time_zones() {
+    use chrono::Timelike;
+    use types::{read_preferences_str, Options};
+    let options = Options {
+        preferences: read_preferences_str(&fixtures::test_time_zones_preferences()),
+        ..fixtures::test_options()
+    };
+    let (_, solution) = fixtures::solve_input(&fixtures::test_time_zones_input(), options);
+    let mut hours = solution
+        .values()
+        .map(|c| c.start.with_timezone(&::chrono_tz::UTC).hour())
+        .collect::<Vec<u32>>();
+    hours.sort();
+    // Each meeting is either late in London or early in San Francisco, they
+    // take turns
+    assert_eq!(hours, vec![16, 17]);
+}
@@ -1,7 +1,14 @@
-use types::{DesiredMeeting, MeetingCandidate, MeetingsTree};
+use backend::{CalendarBackend, NewEvent};
+use types::{CalendarSettings, DesiredMeeting, HourRange, HourSpec, MeetingCandidate, MeetingsTree};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ops::Range;
 use types;
 use chrono;
+use solver::{build_intersections_pairs, solve_with_builtin_solver, SolverInput};
+use std::io::prelude::*;
+use std::net::TcpListener;
+use std::thread;
 
 #[allow(dead_code)]
 pub fn test_input() -> String {
@@ -43,6 +50,160 @@ pub fn test_invalid_input() -> String {
         .to_string()
 }
 
+// Two meetings with different attendees and a single possible slot, at the
+// same time
+#[allow(dead_code)]
+pub fn test_parallel_input() -> String {
+    "- title: first
+  description: description
+  attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-02-08T10:00:00
+  max_date: 2018-02-08T10:30:00
+- title: second
+  description: description
+  attendees:
+  - contact@laurent.com
+  min_date: 2018-02-08T10:00:00
+  max_date: 2018-02-08T10:30:00
+"
+        .to_string()
+}
+
+#[allow(dead_code)]
+pub fn test_optional_input() -> String {
+    "- title: optional
+  description: description
+  attendees:
+  - contact@laurent.com
+  optional_attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-02-08T10:00:00
+  max_date: 2018-02-08T16:00:00
+"
+        .to_string()
+}
+
+#[allow(dead_code)]
+pub fn test_recurring_input() -> String {
+    "- title: weekly
+  description: description
+  attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-02-22T10:00:00
+  max_date: 2018-02-22T12:00:00
+  recurrence:
+    weeks: 4
+    min_free_fraction: 0.75
+- title: daily
+  description: description
+  attendees:
+  - contact@laurent.com
+  min_date: 2018-02-22T10:00:00
+  max_date: 2018-02-22T12:00:00
+  recurrence:
+    rrule: FREQ=DAILY;INTERVAL=2;COUNT=5
+"
+        .to_string()
+}
+
+// The second occurrence of these meetings conflicts with the meeting returned
+// by fetch_results
+#[allow(dead_code)]
+pub fn test_weekly_input() -> String {
+    "- title: every week
+  description: description
+  attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-02-01T14:00:00
+  max_date: 2018-02-01T14:30:00
+  recurrence:
+    weeks: 2
+- title: most weeks
+  description: description
+  attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-02-01T14:00:00
+  max_date: 2018-02-01T14:30:00
+  recurrence:
+    weeks: 2
+    min_free_fraction: 0.5
+"
+        .to_string()
+}
+
+#[allow(dead_code)]
+pub fn test_dependent_input() -> String {
+    "- title: review
+  description: description
+  attendees:
+  - contact@laurent.com
+  min_date: 2018-02-08T10:00:00
+  max_date: 2018-02-08T12:00:00
+  after: kickoff
+  min_gap: 60
+- title: kickoff
+  description: description
+  attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-02-08T10:00:00
+  max_date: 2018-02-08T12:00:00
+"
+        .to_string()
+}
+
+// Laurent is busy from 14:00, the coding session has to come first
+#[allow(dead_code)]
+pub fn test_loop_input() -> String {
+    "- title: onsite
+  description: description
+  attendees:
+  - candidate@bar.com
+  min_date: 2018-02-08T13:00:00
+  max_date: 2018-02-08T14:30:00
+  sessions:
+  - title: design
+    interviewers:
+    - contact@laurent.com
+  - title: coding
+    interviewers:
+    - laurent.charignon@foo.com
+    duration: 60
+  duration: 30
+"
+        .to_string()
+}
+
+// Laurent is the best pick for both reviews, being busy from 14:00 makes his
+// day less fragmented
+#[allow(dead_code)]
+pub fn test_pool_input() -> String {
+    "- title: review1
+  description: description
+  attendees:
+  - candidate1@bar.com
+  pools:
+  - pick: 1
+    from:
+    - laurent.charignon@foo.com
+    - contact@laurent.com
+  min_date: 2018-02-08T13:00:00
+  max_date: 2018-02-08T16:00:00
+- title: review2
+  description: description
+  attendees:
+  - candidate2@bar.com
+  pools:
+  - pick: 1
+    from:
+    - laurent.charignon@foo.com
+    - contact@laurent.com
+  min_date: 2018-02-08T13:00:00
+  max_date: 2018-02-08T16:00:00
+"
+        .to_string()
+}
+
 #[allow(dead_code)]
 pub fn fetch_results(emails: Vec<String>) -> HashMap<String, MeetingsTree> {
     let mut l = HashMap::new();
@@ -62,6 +223,39 @@ pub fn fetch_results(emails: Vec<String>) -> HashMap<String, MeetingsTree> {
     l
 }
 
+#[allow(dead_code)]
+pub fn test_limits_input() -> String {
+    "- title: title
+  description: description
+  attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-02-08T13:00:00
+  max_date: 2018-02-09T17:00:00
+  duration: 60
+"
+        .to_string()
+}
+
+// Laurent is busy from 9 to 12 on Thursday 2018-02-08
+#[allow(dead_code)]
+pub fn fetch_busy_morning(emails: Vec<String>) -> HashMap<String, MeetingsTree> {
+    let mut l = HashMap::new();
+    for i in emails {
+        let mut k = MeetingsTree::new();
+        if i == "laurent.charignon@foo.com" {
+            let from = "2018-02-08T09:00:00-08:00"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .expect("Error from");
+            let to = "2018-02-08T12:00:00-08:00"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .expect("error to");
+            k.insert(from..to, "meeting".to_string())
+        }
+        l.insert(i, k);
+    }
+    l
+}
+
 #[allow(dead_code)]
 pub fn test_config() -> String {
     "
@@ -88,6 +282,229 @@ ignore_all_day_events: false
         .to_string()
 }
 
+#[allow(dead_code)]
+pub fn test_rooms_config() -> String {
+    "
+rooms:
+  - email: thirty@jam.com
+    capacity: 30
+    tags: [video, whiteboard]
+  - email: four@jam.com
+    capacity: 4
+  - email: eight@jam.com
+    capacity: 8
+    tags: [video, 'building:hq']
+small_rooms:
+  - foo@bar.com
+large_rooms:
+  - bozorg@jam.com
+buffers:
+  default_minutes: 10
+  attendees:
+    alice@foo.com: 5
+  rooms:
+    four@jam.com: 15
+"
+        .to_string()
+}
+
+#[allow(dead_code)]
+pub fn test_scoring_config() -> String {
+    "
+scoring:
+  clustering:
+    - within_minutes: 60
+      points: 10
+  preferred_hours:
+    - from:
+        hours: 9
+        minutes: 0
+        seconds: 0
+      to:
+        hours: 12
+        minutes: 0
+        seconds: 0
+      points: 50
+  earlier_date_points: 5
+  first_slot_penalty: 3
+  last_slot_penalty: 4
+"
+        .to_string()
+}
+
+// Laurent does not meet after 4pm nor on Fridays, his contact likes to meet
+// before lunch
+#[allow(dead_code)]
+pub fn test_preferences() -> String {
+    "
+laurent.charignon@foo.com:
+  disliked_days: [5]
+  no_meeting_hours:
+    - from:
+        hours: 16
+        minutes: 0
+        seconds: 0
+      to:
+        hours: 23
+        minutes: 0
+        seconds: 0
+contact@laurent.com:
+  preferred_hours:
+    - from:
+        hours: 11
+        minutes: 0
+        seconds: 0
+      to:
+        hours: 12
+        minutes: 0
+        seconds: 0
+      points: 2000
+"
+        .to_string()
+}
+
+// Working hours in London and San Francisco only overlap from 16:00 to
+// 18:00 UTC, the first hour is early in San Francisco and the second one is
+// late in London
+#[allow(dead_code)]
+pub fn test_time_zones_preferences() -> String {
+    "lon1@foo.com:
+  timezone: Europe/London
+  working_hours:
+    from:
+      hours: 9
+      minutes: 0
+      seconds: 0
+    to:
+      hours: 18
+      minutes: 0
+      seconds: 0
+lon2@foo.com:
+  timezone: Europe/London
+  working_hours:
+    from:
+      hours: 9
+      minutes: 0
+      seconds: 0
+    to:
+      hours: 18
+      minutes: 0
+      seconds: 0
+sf1@foo.com:
+  timezone: America/Los_Angeles
+  working_hours:
+    from:
+      hours: 8
+      minutes: 0
+      seconds: 0
+    to:
+      hours: 17
+      minutes: 0
+      seconds: 0
+sf2@foo.com:
+  timezone: America/Los_Angeles
+  working_hours:
+    from:
+      hours: 8
+      minutes: 0
+      seconds: 0
+    to:
+      hours: 17
+      minutes: 0
+      seconds: 0
+"
+        .to_string()
+}
+
+// London has no working hours in its calendar, the default ones are used
+#[allow(dead_code)]
+pub fn fetch_calendar_settings(emails: Vec<String>) -> HashMap<String, CalendarSettings> {
+    let hours = |from: u32, to: u32| HourRange {
+        from: HourSpec {
+            hours: from,
+            minutes: 0,
+            seconds: 0,
+        },
+        to: HourSpec {
+            hours: to,
+            minutes: 0,
+            seconds: 0,
+        },
+    };
+    emails
+        .into_iter()
+        .filter_map(|k| {
+            let settings = if k.starts_with("lon") {
+                CalendarSettings {
+                    timezone: ::chrono_tz::Europe::London,
+                    working_hours: None,
+                    working_days: Vec::new(),
+                }
+            } else if k.starts_with("sf") {
+                CalendarSettings {
+                    timezone: ::chrono_tz::America::Los_Angeles,
+                    working_hours: Some(hours(8, 17)),
+                    working_days: vec![1, 2, 3, 4, 5],
+                }
+            } else {
+                return None;
+            };
+            Some((k, settings))
+        })
+        .collect()
+}
+
+#[allow(dead_code)]
+pub fn test_time_zones_input() -> String {
+    "- title: sync1
+  description: description
+  attendees:
+  - lon1@foo.com
+  - sf1@foo.com
+  min_date: 2018-02-08T14:00:00
+  max_date: 2018-02-08T20:00:00
+  duration: 60
+  timezone: UTC
+- title: sync2
+  description: description
+  attendees:
+  - lon2@foo.com
+  - sf2@foo.com
+  min_date: 2018-02-08T14:00:00
+  max_date: 2018-02-08T20:00:00
+  duration: 60
+  timezone: UTC
+"
+        .to_string()
+}
+
+// Options reading the calendars of fetch_results, the dates of the test
+// inputs being in the past
+#[allow(dead_code)]
+pub fn test_options() -> types::Options {
+    types::Options {
+        backend: Box::new(FakeBackend::new(fetch_results)),
+        consider_meetings_in_the_past: true,
+        ..Default::default()
+    }
+}
+
+#[allow(dead_code)]
+pub fn solver_input(input: &str, options: &types::Options) -> SolverInput {
+    SolverInput::new_from_desired_meetings_and_opts(types::read_input_str(input), options)
+}
+
+// The meetings the builtin solver books for an input, with the solver input
+#[allow(dead_code)]
+pub fn solve_input(
+    input: &str,
+    options: types::Options,
+) -> (SolverInput, HashMap<DesiredMeeting, MeetingCandidate>) {
+    let input = solver_input(input, &options);
+    let solution = solve_with_builtin_solver(&input).expect("Problem should be feasible");
+    (input, solution)
+}
+
 #[allow(dead_code)]
 pub fn test_desired_meetings() -> Vec<DesiredMeeting> {
     let a = types::read_input_str(&test_input());
@@ -100,6 +517,11 @@ pub fn test_desired_meeting() -> DesiredMeeting {
     a[1].clone()
 }
 
+#[allow(dead_code)]
+pub fn no_buffer(_: &str) -> chrono::Duration {
+    chrono::Duration::zero()
+}
+
 #[allow(dead_code)]
 pub fn sample_cbc_solution() -> String {
     "Optimal - objective value -2422.00000000
@@ -116,7 +538,10 @@ pub fn sample_candidate_a() -> MeetingCandidate {
         start: chrono::Utc::now(),
         end: chrono::Utc::now(),
         room: Some("foo".to_string()),
+        attendees: vec!["laurent.charignon@foo.com".to_string()],
         score: 23,
+        recurrences: Vec::new(),
+        sessions: Vec::new(),
     }
 }
 
@@ -128,6 +553,378 @@ pub fn sample_candidate_b() -> MeetingCandidate {
         start: chrono::Utc::now(),
         end: chrono::Utc::now(),
         room: Some("bar".to_string()),
+        attendees: vec!["contact@laurent.com".to_string()],
         score: 23,
+        recurrences: Vec::new(),
+        sessions: Vec::new(),
+    }
+}
+
+// Two desired meetings, each with a single candidate, taking place at the
+// same time with the same attendee
+#[allow(dead_code)]
+pub fn conflicting_solver_input() -> SolverInput {
+    let mut input = SolverInput::new();
+    let mut candidate_a = sample_candidate_a();
+    let mut candidate_b = sample_candidate_b();
+    candidate_a.end = candidate_a.start + chrono::Duration::minutes(30);
+    candidate_b.id = "id0".to_string();
+    candidate_b.start = candidate_a.start;
+    candidate_b.end = candidate_a.end;
+    candidate_b.attendees = candidate_a.attendees.clone();
+
+    input.candidates.insert("id10873".to_string(), candidate_a);
+    input.candidates.insert("id0".to_string(), candidate_b);
+    input.desired_meetings.extend(test_desired_meetings());
+    input
+        .candidate_per_desired_meeting
+        .insert("title".to_string(), vec!["id10873".to_string()]);
+    input
+        .candidate_per_desired_meeting
+        .insert("title2".to_string(), vec!["id0".to_string()]);
+    input.intersections = build_intersections_pairs(&input.candidates, &no_buffer);
+    input
+}
+
+// Two pages of events of the calendar API, the first one pointing to the
+// second
+#[allow(dead_code)]
+pub fn events_pages() -> Vec<String> {
+    vec![
+        r#"{"timeZone": "America/Los_Angeles", "nextPageToken": "page2", "items": [
+  {"id": "a", "start": {"dateTime": "2018-02-08T09:00:00-08:00"},
+   "end": {"dateTime": "2018-02-08T10:00:00-08:00"}}]}"#
+            .to_string(),
+        r#"{"timeZone": "America/Los_Angeles", "items": [
+  {"id": "b", "start": {"dateTime": "2018-02-09T09:00:00-08:00"},
+   "end": {"dateTime": "2018-02-09T10:00:00-08:00"}},
+  {"id": "c", "start": {"date": "2018-02-12"}, "end": {"date": "2018-02-13"}}]}"#
+            .to_string(),
+    ]
+}
+
+// A stand-in for a calendar server on a local port, answering one request per
+// body in order. Returns its base url and the requests it got.
+#[allow(dead_code)]
+pub fn serve_api(bodies: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+    serve_api_with_status(bodies.into_iter().map(|k| ("200 OK", k)).collect())
+}
+
+// Same with the status of every answer, like "404 Not Found"
+#[allow(dead_code)]
+pub fn serve_api_with_status(
+    answers: Vec<(&'static str, String)>,
+) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Cannot listen");
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for (status, body) in answers {
+            let (mut stream, _) = listener.accept().expect("Cannot accept");
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                let n = stream.read(&mut buffer).expect("Cannot read request");
+                request.extend_from_slice(&buffer[..n]);
+            }
+            // Then the body, if any
+            let head_length = String::from_utf8_lossy(&request).find("\r\n\r\n").unwrap() + 4;
+            let content_length = String::from_utf8_lossy(&request[..head_length])
+                .lines()
+                .filter_map(|k| {
+                    let mut kv = k.splitn(2, ':');
+                    match (kv.next(), kv.next()) {
+                        (Some(key), Some(v)) if key.eq_ignore_ascii_case("content-length") => {
+                            v.trim().parse::<usize>().ok()
+                        }
+                        _ => None,
+                    }
+                })
+                .next()
+                .unwrap_or(0);
+            while request.len() < head_length + content_length {
+                let n = stream.read(&mut buffer).expect("Cannot read request");
+                request.extend_from_slice(&buffer[..n]);
+            }
+            requests.push(String::from_utf8_lossy(&request).to_string());
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            ).expect("Cannot answer");
+        }
+        requests
+    });
+    (url, server)
+}
+
+// A calendar provider answering from fixtures and keeping what is booked
+#[allow(dead_code)]
+pub struct FakeBackend {
+    pub availability: fn(Vec<String>) -> HashMap<String, MeetingsTree>,
+    pub settings: fn(Vec<String>) -> HashMap<String, CalendarSettings>,
+    pub rooms: Vec<types::Room>,
+    pub created: RefCell<Vec<NewEvent>>,
+    pub deleted: RefCell<Vec<String>>,
+}
+
+#[allow(dead_code)]
+impl FakeBackend {
+    pub fn new(availability: fn(Vec<String>) -> HashMap<String, MeetingsTree>) -> FakeBackend {
+        FakeBackend {
+            availability,
+            settings: |_| HashMap::new(),
+            rooms: Vec::new(),
+            created: RefCell::new(Vec::new()),
+            deleted: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+// Event ids are their index in created
+impl CalendarBackend for FakeBackend {
+    fn fetch_availability(
+        &self,
+        people: Vec<String>,
+        _: &Range<chrono::DateTime<chrono::Utc>>,
+        _: bool,
+        _: bool,
+    ) -> HashMap<String, MeetingsTree> {
+        (self.availability)(people)
+    }
+
+    fn fetch_settings(
+        &self,
+        people: Vec<String>,
+        _: &Range<chrono::DateTime<chrono::Utc>>,
+    ) -> HashMap<String, CalendarSettings> {
+        (self.settings)(people)
+    }
+
+    fn create_events(&self, events: &[NewEvent]) -> Vec<String> {
+        let mut created = self.created.borrow_mut();
+        events
+            .iter()
+            .map(|e| {
+                created.push(e.clone());
+                (created.len() - 1).to_string()
+            })
+            .collect()
+    }
+
+    fn update_event(&self, id: &str, event: &NewEvent) {
+        let k = id.parse::<usize>().expect("Unknown event");
+        self.created.borrow_mut()[k] = event.clone();
+    }
+
+    fn delete_event(&self, id: &str) {
+        self.deleted.borrow_mut().push(id.to_string());
+    }
+
+    fn list_rooms(&self) -> Vec<types::Room> {
+        self.rooms.clone()
     }
 }
+
+// What a CalDAV server answers to a calendar-query on the calendar of Laurent
+#[allow(dead_code)]
+pub fn caldav_events() -> String {
+    let events = "BEGIN:VCALENDAR&#13;
+VERSION:2.0&#13;
+BEGIN:VEVENT&#13;
+UID:accepted&#13;
+DTSTART;TZID=America/Los_Angeles:20180208T090000&#13;
+DTEND;TZID=America/Los_Angeles:20180208T100000&#13;
+ATTENDEE;PARTSTAT=ACCEPTED:mailto:laurent.charignon@foo.com&#13;
+END:VEVENT&#13;
+BEGIN:VEVENT&#13;
+UID:declined&#13;
+DTSTART:20180209T170000Z&#13;
+DTEND:20180209T180000Z&#13;
+ATTENDEE;PARTSTAT=DECLINED:mailto:laurent.charignon@foo.com&#13;
+END:VEVENT&#13;
+BEGIN:VEVENT&#13;
+UID:organized&#13;
+DTSTART:20180212T170000Z&#13;
+DURATION:PT30M&#13;
+ATTENDEE;PARTSTAT=NEEDS-ACTION:mailto:contact@laurent.com&#13;
+END:VEVENT&#13;
+BEGIN:VEVENT&#13;
+UID:transparent&#13;
+DTSTART:20180213T170000Z&#13;
+DTEND:20180213T180000Z&#13;
+TRANSP:TRANSPARENT&#13;
+END:VEVENT&#13;
+BEGIN:VEVENT&#13;
+UID:all-day&#13;
+DTSTART;VALUE=DATE:20180214&#13;
+END:VEVENT&#13;
+BEGIN:VEVENT&#13;
+UID:floating&#13;
+DTSTART:20180213T100000&#13;
+DTEND:20180213T110000&#13;
+END:VEVENT&#13;
+END:VCALENDAR&#13;
+";
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/laurent.charignon@foo.com/calendar/event.ics</href>
+    <propstat>
+      <prop>
+        <C:calendar-data>{}</C:calendar-data>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+</multistatus>"#,
+        events
+    )
+}
+
+// The calendar-timezone of the calendar of Laurent, in Paris
+#[allow(dead_code)]
+pub fn caldav_timezone() -> String {
+    r#"<?xml version="1.0" encoding="utf-8"?>
+<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/laurent.charignon@foo.com/calendar/</href>
+    <propstat>
+      <prop>
+        <C:calendar-timezone>BEGIN:VCALENDAR&#13;
+VERSION:2.0&#13;
+BEGIN:VTIMEZONE&#13;
+TZID:Europe/Paris&#13;
+END:VTIMEZONE&#13;
+END:VCALENDAR&#13;
+</C:calendar-timezone>
+      </prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+</multistatus>"#
+        .to_string()
+}
+
+#[allow(dead_code)]
+pub fn caldav_free_busy() -> String {
+    "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VFREEBUSY\r
+DTSTART:20180208T000000Z\r
+DTEND:20180215T000000Z\r
+FREEBUSY;FBTYPE=BUSY:20180208T170000Z/20180208T180000Z,20180209T170000Z/PT30M\r
+FREEBUSY;FBTYPE=FREE:20180210T170000Z/20180210T180000Z\r
+END:VFREEBUSY\r
+END:VCALENDAR\r
+"
+        .to_string()
+}
+
+// A calendar exported to a file: a daily standup in Paris with one day off,
+// a declined event and an all day event
+#[allow(dead_code)]
+pub fn ics_calendar() -> String {
+    "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+X-WR-TIMEZONE:Europe/Paris\r
+BEGIN:VEVENT\r
+UID:standup\r
+DTSTART:20180201T100000\r
+DTEND:20180201T103000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR\r
+EXDATE:20180212T100000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:declined\r
+DTSTART:20180209T170000Z\r
+DTEND:20180209T180000Z\r
+ATTENDEE;PARTSTAT=DECLINED:mailto:laurent.charignon@foo.com\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:all-day\r
+DTSTART;VALUE=DATE:20180214\r
+END:VEVENT\r
+END:VCALENDAR\r
+"
+        .to_string()
+}
+
+// Recorded answer of microsoft graph to getSchedule
+#[allow(dead_code)]
+pub fn graph_schedules() -> String {
+    r#"{
+  "@odata.context": "https://graph.microsoft.com/v1.0/$metadata#Collection(microsoft.graph.scheduleInformation)",
+  "value": [
+    {
+      "scheduleId": "laurent.charignon@foo.com",
+      "availabilityView": "000220000",
+      "scheduleItems": [
+        {
+          "isPrivate": false,
+          "status": "busy",
+          "subject": "Lunch",
+          "start": {"dateTime": "2018-02-08T17:00:00.0000000", "timeZone": "UTC"},
+          "end": {"dateTime": "2018-02-08T18:00:00.0000000", "timeZone": "UTC"}
+        },
+        {
+          "isPrivate": false,
+          "status": "tentative",
+          "start": {"dateTime": "2018-02-08T18:00:00.0000000", "timeZone": "UTC"},
+          "end": {"dateTime": "2018-02-08T18:30:00.0000000", "timeZone": "UTC"}
+        },
+        {
+          "isPrivate": false,
+          "status": "workingElsewhere",
+          "start": {"dateTime": "2018-02-09T16:00:00.0000000", "timeZone": "UTC"},
+          "end": {"dateTime": "2018-02-10T01:00:00.0000000", "timeZone": "UTC"}
+        }
+      ],
+      "workingHours": {
+        "daysOfWeek": ["monday", "tuesday", "wednesday", "thursday", "friday"],
+        "startTime": "08:00:00.0000000",
+        "endTime": "17:00:00.0000000",
+        "timeZone": {"name": "Pacific Standard Time"}
+      }
+    },
+    {
+      "scheduleId": "contact@laurent.com",
+      "availabilityView": "000000000",
+      "scheduleItems": []
+    },
+    {
+      "scheduleId": "guest@other.com",
+      "availabilityView": "",
+      "error": {
+        "message": "Cannot find the user in the organization",
+        "responseCode": "ErrorMailRecipientNotFound"
+      }
+    }
+  ]
+}"#
+        .to_string()
+}
+
+// Two pages of rooms
+#[allow(dead_code)]
+pub fn graph_rooms() -> Vec<String> {
+    vec![
+        r#"{
+  "value": [
+    {"id": "1", "emailAddress": "four@jam.com", "displayName": "Four", "capacity": 4}
+  ],
+  "@odata.nextLink": "/places/microsoft.graph.room?$skip=1"
+}"#
+            .to_string(),
+        r#"{
+  "value": [
+    {"id": "2", "emailAddress": "eight@jam.com", "displayName": "Eight", "capacity": 8,
+     "tags": ["video"]}
+  ]
+}"#
+            .to_string(),
+    ]
+}
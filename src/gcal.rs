@@ -1,4 +1,5 @@
-use types::{DesiredMeeting, Meeting, MeetingCandidate, MeetingsTree, Solution};
+use backend::{CalendarBackend, NewEvent};
+use types::{CalendarSettings, Meeting, MeetingsTree, Room};
 
 use calendar3;
 use hyper;
@@ -8,7 +9,9 @@ use hyper_rustls;
 use rayon;
 use bio::data_structures::interval_tree::IntervalTree;
 use std::path::Path;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ops::Range;
 use calendar3::CalendarHub;
 use self::oauth2::{read_application_secret, ApplicationSecret, Authenticator,
                    DefaultAuthenticatorDelegate, DiskTokenStorage, FlowType};
@@ -16,8 +19,13 @@ use self::hyper::net::HttpsConnector;
 use self::rayon::prelude::*;
 use chrono::prelude::*;
 use chrono_tz::Tz;
+use fixtures;
 
 const MALFORMED_ERR: &str = "Malformed google event";
+const EVENTS_PER_PAGE: i32 = 250;
+const RESOURCE_CALENDAR_DOMAIN: &str = "@resource.calendar.google.com";
+// The FreeBusy API answers for at most this many calendars per query
+const FREEBUSY_MAX_CALENDARS: usize = 50;
 
 impl Meeting {
     fn from_api(s: calendar3::Event, tz: &Tz) -> Meeting {
@@ -66,80 +74,48 @@ type CalendarHubType = CalendarHub<
     hyper::Client,
     Authenticator<DefaultAuthenticatorDelegate, DiskTokenStorage, hyper::Client>,
 >;
-fn candidate_and_meeting_to_event(
-    desired_meeting: &DesiredMeeting,
-    candidate: &MeetingCandidate,
-    include_tagline: bool,
-) -> calendar3::Event {
-    let mut attendees: Vec<String> = Vec::new();
-    attendees.extend(desired_meeting.attendees.clone());
-    if let Some(room) = candidate.room.clone() {
-        attendees.push(room);
-    }
-
-    let attendees = Some(
-        attendees
-            .iter()
-            .map(|k| calendar3::EventAttendee {
-                email: Some(k.to_string()),
-                response_status: Some("needsAction".to_string()),
-                ..Default::default()
-            })
-            .collect::<Vec<calendar3::EventAttendee>>(),
-    );
-
-    let description = if include_tagline {
-        format!(
-            "{}\n{}",
-            desired_meeting.description,
-            "=> Booked by Optirust: https://github.com/charignon/optirust"
-        )
-    } else {
-        desired_meeting.description.clone()
+fn to_api_event(e: &NewEvent) -> calendar3::Event {
+    let attendee = |email: &str, optional: Option<bool>| calendar3::EventAttendee {
+        email: Some(email.to_string()),
+        response_status: Some("needsAction".to_string()),
+        optional,
+        ..Default::default()
     };
+    let mut attendees = e.attendees
+        .iter()
+        .chain(e.room.iter())
+        .map(|k| attendee(k, None))
+        .collect::<Vec<calendar3::EventAttendee>>();
+    attendees.extend(e.optional_attendees.iter().map(|k| attendee(k, Some(true))));
+
+    // Recurring events need a timezone to be expanded in
+    let time_zone = e.recurrence
+        .as_ref()
+        .map(|_| e.timezone.name().to_string());
 
     calendar3::Event {
-        attendees,
+        attendees: Some(attendees),
         start: Some(calendar3::EventDateTime {
-            date_time: Some(candidate.start.to_rfc3339()),
+            date_time: Some(e.start.to_rfc3339()),
+            time_zone: time_zone.clone(),
             ..Default::default()
         }),
         end: Some(calendar3::EventDateTime {
-            date_time: Some(candidate.end.to_rfc3339()),
+            date_time: Some(e.end.to_rfc3339()),
+            time_zone,
             ..Default::default()
         }),
-        description: Some(description),
+        recurrence: e.recurrence.clone().map(|k| vec![k]),
+        description: Some(e.description.to_string()),
         reminders: Some(calendar3::EventReminders {
             use_default: Some(true),
             overrides: None,
         }),
-        summary: Some(desired_meeting.title.to_string()),
+        summary: Some(e.title.to_string()),
         ..Default::default()
     }
 }
 
-pub fn book_with_api(s: &Solution, include_tagline: bool) {
-    let mut es: Vec<calendar3::Event> = Vec::new();
-
-    for (desired_meeting, candidate) in &s.candidates {
-        es.push(candidate_and_meeting_to_event(
-            desired_meeting,
-            candidate,
-            include_tagline,
-        ));
-    }
-
-    es.par_iter()
-        .map(|e| {
-            get_calendar_hub()
-                .events()
-                .insert(e.clone(), "primary")
-                .doit()
-                .expect("Cannot reach the google calendar API")
-        })
-        .collect::<Vec<(hyper::client::Response, calendar3::Event)>>();
-}
-
 // Return a CalendarHub object to work with the google calendar API
 pub fn get_calendar_hub() -> CalendarHubType {
     let secret = read_client_secret(CLIENT_SECRET_FILE);
@@ -212,58 +188,100 @@ fn meetings_to_tree(meetings: &[Meeting]) -> MeetingsTree {
     intervals
 }
 
-fn fetch_one_availability_with_api(
+// The timezone of a calendar, given with its events
+fn calendar_timezone(time_zone: Option<String>) -> Tz {
+    time_zone
+        .expect(MALFORMED_ERR)
+        .parse()
+        .expect("Cannot decode timezone")
+}
+
+// The events of a calendar within a window, with its timezone
+struct Listing {
+    window: Range<DateTime<chrono::Utc>>,
+    events: Vec<calendar3::Event>,
+    timezone: Tz,
+}
+
+// All the events of person within the window, following the pages of the
+// API. Fails when the calendar is not shared with the user.
+fn list_events_with_api<A: oauth2::GetToken>(
     person: &str,
-    hub: &CalendarHubType,
+    window: &Range<DateTime<chrono::Utc>>,
+    hub: &CalendarHub<hyper::Client, A>,
+) -> Result<Listing, String> {
+    println!("Fetching for {:?}", person);
+    let mut events: Vec<calendar3::Event> = Vec::new();
+    let mut page_token: Option<String> = None;
+    let timezone = loop {
+        let mut call = hub.events()
+            .list(person)
+            .max_results(EVENTS_PER_PAGE)
+            .order_by("startTime")
+            .single_events(true)
+            .time_min(&window.start.to_rfc3339())
+            .time_max(&window.end.to_rfc3339());
+        if let Some(ref token) = page_token {
+            call = call.page_token(token);
+        }
+        let (_, page) = call.doit().map_err(|e| format!("{:?}", e))?;
+        events.extend(page.items.unwrap_or_default());
+        page_token = page.next_page_token;
+        if page_token.is_none() {
+            break page.time_zone;
+        }
+    };
+    Ok(Listing {
+        window: window.clone(),
+        events,
+        timezone: calendar_timezone(timezone),
+    })
+}
+
+fn listing_to_tree(
+    person: &str,
+    listing: &Listing,
     ignore_all_day_events: bool,
     ignore_meetings_with_no_response: bool,
 ) -> MeetingsTree {
-    println!("Fetching for {:?}", person);
-    let result = hub.events()
-        .list(person)
-        .max_results(200)
-        .order_by("startTime")
-        .single_events(true)
-        .time_min(&chrono::Utc::now().to_rfc3339())
-        .doit();
-    let (_, events) = result.expect("Cannot reach google API");
-    let timezone: Tz = events
-        .time_zone
-        .expect(MALFORMED_ERR)
-        .parse()
-        .expect("Cannot decode timezone");
-    let events: Vec<calendar3::Event> = events.items.expect(MALFORMED_ERR);
-
-    meetings_to_tree(&events
-        .into_iter()
+    meetings_to_tree(&listing
+        .events
+        .iter()
         .filter(|l| {
             valid_api_meeting(
                 person,
-                l.clone(),
+                (*l).clone(),
                 ignore_all_day_events,
                 ignore_meetings_with_no_response,
             )
         })
-        .map(|o| Meeting::from_api(o, &timezone))
+        .cloned()
+        .map(|o| Meeting::from_api(o, &listing.timezone))
         .collect::<Vec<Meeting>>())
 }
 
-pub fn fetch_availability_with_api(
+// The listings already made for the settings within the same window are
+// used instead of listing the events again
+fn fetch_availability_with_api(
     people: Vec<String>,
+    window: &Range<DateTime<chrono::Utc>>,
     ignore_all_day_events: bool,
     ignore_meetings_with_no_response: bool,
+    listings: &HashMap<String, Listing>,
 ) -> HashMap<String, MeetingsTree> {
     let mut res: HashMap<String, IntervalTree<DateTime<chrono::Utc>, String>> = HashMap::new();
 
     let availability = people
         .par_iter()
         .map(|a| {
-            fetch_one_availability_with_api(
-                a,
-                &get_calendar_hub(),
-                ignore_all_day_events,
-                ignore_meetings_with_no_response,
-            )
+            let tree = |listing: &Listing| {
+                listing_to_tree(a, listing, ignore_all_day_events, ignore_meetings_with_no_response)
+            };
+            match listings.get(a) {
+                Some(listing) if listing.window == *window => tree(listing),
+                _ => tree(&list_events_with_api(a, window, &get_calendar_hub())
+                    .expect("Cannot reach google API")),
+            }
         })
         .collect::<Vec<MeetingsTree>>()
         .into_iter();
@@ -276,6 +294,272 @@ pub fn fetch_availability_with_api(
     res
 }
 
+fn freebusy_to_trees(response: calendar3::FreeBusyResponse) -> HashMap<String, MeetingsTree> {
+    let parse = |t: Option<String>| {
+        chrono::DateTime::parse_from_rfc3339(&t.expect(MALFORMED_ERR))
+            .expect(MALFORMED_ERR)
+            .with_timezone(&chrono::Utc)
+    };
+    response
+        .calendars
+        .expect(MALFORMED_ERR)
+        .into_iter()
+        .map(|(person, calendar)| {
+            // Like a calendar not shared with the user, the person is free
+            if let Some(errors) = calendar.errors {
+                eprintln!(
+                    "WARNING: cannot get the busy times of {}, taken as free: {:?}",
+                    person, errors
+                );
+                return (person, IntervalTree::new());
+            }
+            let meetings = calendar
+                .busy
+                .unwrap_or_default()
+                .into_iter()
+                .enumerate()
+                .map(|(k, period)| Meeting {
+                    id: format!("busy_{}", k),
+                    start: parse(period.start),
+                    end: parse(period.end),
+                })
+                .collect::<Vec<Meeting>>();
+            (person, meetings_to_tree(&meetings))
+        })
+        .collect()
+}
+
+fn query_freebusy_with_api(
+    people: &[String],
+    window: &Range<DateTime<chrono::Utc>>,
+    hub: &CalendarHubType,
+) -> HashMap<String, MeetingsTree> {
+    println!("Fetching busy times for {:?}", people);
+    let request = calendar3::FreeBusyRequest {
+        time_min: Some(window.start.to_rfc3339()),
+        time_max: Some(window.end.to_rfc3339()),
+        items: Some(
+            people
+                .iter()
+                .map(|k| calendar3::FreeBusyRequestItem {
+                    id: Some(k.to_string()),
+                })
+                .collect(),
+        ),
+        ..Default::default()
+    };
+    let (_, response) = hub.freebusy()
+        .query(request)
+        .doit()
+        .expect("Cannot reach google API");
+    freebusy_to_trees(response)
+}
+
+// Only the busy times of the calendars within the window, which needs no
+// access to the details of the events and takes one query per 50 people.
+// The API does not tell the responses nor the all day events apart, so both
+// flags are ignored: everything busy counts.
+fn fetch_availability_with_freebusy(
+    people: Vec<String>,
+    window: &Range<DateTime<chrono::Utc>>,
+    _: bool,
+    _: bool,
+) -> HashMap<String, MeetingsTree> {
+    people
+        .par_chunks(FREEBUSY_MAX_CALENDARS)
+        .map(|k| query_freebusy_with_api(k, window, &get_calendar_hub()))
+        .reduce(HashMap::new, |mut a, b| {
+            a.extend(b);
+            a
+        })
+}
+
+// The timezone comes with the events of the calendar, so the events of the
+// window are listed once for both the settings and the availability. Google
+// calendar does not expose working hours nor working locations in its API,
+// the calendar_working_hours of the config stand for them. People whose
+// calendar is not shared with the user, like guests from other companies, are
+// left out.
+fn list_settings_with_api<A: oauth2::GetToken>(
+    person: &str,
+    window: &Range<DateTime<chrono::Utc>>,
+    hub: &CalendarHub<hyper::Client, A>,
+) -> Option<Listing> {
+    match list_events_with_api(person, window, hub) {
+        Ok(listing) => Some(listing),
+        Err(e) => {
+            eprintln!("WARNING: cannot read the calendar of {}: {}", person, e);
+            None
+        }
+    }
+}
+
+// With the FreeBusy API the events are not listed, a single one is enough
+// for the timezone
+fn fetch_one_timezone_with_api<A: oauth2::GetToken>(
+    person: &str,
+    hub: &CalendarHub<hyper::Client, A>,
+) -> Option<Tz> {
+    let result = hub.events()
+        .list(person)
+        .max_results(1)
+        .single_events(true)
+        .time_min(&chrono::Utc::now().to_rfc3339())
+        .doit();
+    match result {
+        Ok((_, page)) => Some(calendar_timezone(page.time_zone)),
+        Err(e) => {
+            eprintln!("WARNING: cannot read the calendar of {}: {:?}", person, e);
+            None
+        }
+    }
+}
+
+fn timezone_settings(timezone: Tz) -> CalendarSettings {
+    CalendarSettings {
+        timezone,
+        working_hours: None,
+        working_days: Vec::new(),
+    }
+}
+
+// Rooms are the resource calendars in the calendar list of the user, how many
+// people they fit is unknown
+fn list_rooms_with_api(hub: &CalendarHubType) -> Vec<Room> {
+    let mut res = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut call = hub.calendar_list().list();
+        if let Some(ref token) = page_token {
+            call = call.page_token(token);
+        }
+        let (_, page) = call.doit().expect("Cannot reach google API");
+        res.extend(
+            page.items
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|k| k.id)
+                .filter(|k| k.ends_with(RESOURCE_CALENDAR_DOMAIN))
+                .map(|email| Room {
+                    email,
+                    capacity: None,
+                    tags: Vec::new(),
+                }),
+        );
+        page_token = page.next_page_token;
+        if page_token.is_none() {
+            return res;
+        }
+    }
+}
+
+// Google calendar, meetings are booked in the primary calendar of the user
+pub struct GoogleCalendar {
+    // Only read the busy times of the calendars, with the FreeBusy API
+    pub freebusy: bool,
+    // The events listed when fetching the settings, by person
+    listings: RefCell<HashMap<String, Listing>>,
+}
+
+impl GoogleCalendar {
+    pub fn new(freebusy: bool) -> GoogleCalendar {
+        GoogleCalendar {
+            freebusy,
+            listings: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl CalendarBackend for GoogleCalendar {
+    fn fetch_availability(
+        &self,
+        people: Vec<String>,
+        window: &Range<DateTime<chrono::Utc>>,
+        ignore_all_day_events: bool,
+        ignore_meetings_with_no_response: bool,
+    ) -> HashMap<String, MeetingsTree> {
+        if self.freebusy {
+            return fetch_availability_with_freebusy(
+                people,
+                window,
+                ignore_all_day_events,
+                ignore_meetings_with_no_response,
+            );
+        }
+        fetch_availability_with_api(
+            people,
+            window,
+            ignore_all_day_events,
+            ignore_meetings_with_no_response,
+            &self.listings.replace(HashMap::new()),
+        )
+    }
+
+    fn fetch_settings(
+        &self,
+        people: Vec<String>,
+        window: &Range<DateTime<chrono::Utc>>,
+    ) -> HashMap<String, CalendarSettings> {
+        if self.freebusy {
+            return people
+                .par_iter()
+                .filter_map(|a| {
+                    fetch_one_timezone_with_api(a, &get_calendar_hub())
+                        .map(|tz| (a.clone(), timezone_settings(tz)))
+                })
+                .collect::<Vec<(String, CalendarSettings)>>()
+                .into_iter()
+                .collect();
+        }
+        let listings = people
+            .par_iter()
+            .filter_map(|a| {
+                list_settings_with_api(a, window, &get_calendar_hub()).map(|s| (a.clone(), s))
+            })
+            .collect::<Vec<(String, Listing)>>();
+        let settings = listings
+            .iter()
+            .map(|(a, listing)| (a.clone(), timezone_settings(listing.timezone)))
+            .collect();
+        self.listings.replace(listings.into_iter().collect());
+        settings
+    }
+
+    fn create_events(&self, events: &[NewEvent]) -> Vec<String> {
+        events
+            .par_iter()
+            .map(|e| {
+                let (_, event) = get_calendar_hub()
+                    .events()
+                    .insert(to_api_event(e), "primary")
+                    .doit()
+                    .expect("Cannot reach the google calendar API");
+                event.id.expect(MALFORMED_ERR)
+            })
+            .collect()
+    }
+
+    fn update_event(&self, id: &str, event: &NewEvent) {
+        get_calendar_hub()
+            .events()
+            .update(to_api_event(event), "primary", id)
+            .doit()
+            .expect("Cannot reach the google calendar API");
+    }
+
+    fn delete_event(&self, id: &str) {
+        get_calendar_hub()
+            .events()
+            .delete("primary", id)
+            .doit()
+            .expect("Cannot reach the google calendar API");
+    }
+
+    fn list_rooms(&self) -> Vec<Room> {
+        list_rooms_with_api(&get_calendar_hub())
+    }
+}
+
 const CLIENT_SECRET_FILE: &str = "client_secret.json";
 
 // reads the JSON secret file
@@ -283,3 +567,125 @@ fn read_client_secret(file: &str) -> ApplicationSecret {
     read_application_secret(Path::new(file))
         .expect("Cannot find credential, did you create client_secret.json?")
 }
+
+#[test]
+fn test_freebusy_to_trees() {
+    let period = |start: &str, end: &str| calendar3::TimePeriod {
+        start: Some(start.to_string()),
+        end: Some(end.to_string()),
+    };
+    let mut calendars = HashMap::new();
+    calendars.insert(
+        "laurent.charignon@foo.com".to_string(),
+        calendar3::FreeBusyCalendar {
+            busy: Some(vec![
+                period("2018-02-08T09:00:00-08:00", "2018-02-08T10:00:00-08:00"),
+                period("2018-02-08T17:00:00Z", "2018-02-08T18:00:00Z"),
+            ]),
+            errors: None,
+        },
+    );
+    calendars.insert("contact@laurent.com".to_string(), Default::default());
+    calendars.insert(
+        "guest@other.com".to_string(),
+        calendar3::FreeBusyCalendar {
+            busy: None,
+            errors: Some(vec![calendar3::ErrorType {
+                domain: Some("global".to_string()),
+                reason: Some("notFound".to_string()),
+            }]),
+        },
+    );
+    let trees = freebusy_to_trees(calendar3::FreeBusyResponse {
+        calendars: Some(calendars),
+        ..Default::default()
+    });
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let laurent = &trees["laurent.charignon@foo.com"];
+    assert_eq!(laurent.find(at("2018-02-08T09:30:00-08:00")..at("2018-02-08T11:00:00-08:00")).count(), 2);
+    let day = at("2018-02-08T00:00:00Z")..at("2018-02-09T00:00:00Z");
+    assert_eq!(trees["contact@laurent.com"].find(day.clone()).count(), 0);
+    assert_eq!(trees["guest@other.com"].find(day).count(), 0);
+}
+
+#[cfg(test)]
+struct NoAuth;
+
+#[cfg(test)]
+impl oauth2::GetToken for NoAuth {
+    fn token<'b, I, T>(&mut self, _: I) -> Result<oauth2::Token, Box<::std::error::Error>>
+    where
+        T: AsRef<str> + Ord + 'b,
+        I: IntoIterator<Item = &'b T>,
+    {
+        Ok(oauth2::Token {
+            access_token: "token".to_string(),
+            refresh_token: String::new(),
+            token_type: "Bearer".to_string(),
+            expires_in: None,
+            expires_in_timestamp: None,
+        })
+    }
+
+    fn api_key(&mut self) -> Option<String> {
+        None
+    }
+}
+
+#[test]
+fn test_fetch_follows_pages_within_window() {
+    let (url, server) = fixtures::serve_api(fixtures::events_pages());
+    let mut hub = CalendarHub::new(hyper::Client::new(), NoAuth);
+    hub.base_url(url);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let window = at("2018-02-08T00:00:00Z")..at("2018-02-15T00:00:00Z");
+    let listing = list_events_with_api("laurent.charignon@foo.com", &window, &hub).unwrap();
+    let tree = listing_to_tree("laurent.charignon@foo.com", &listing, true, true);
+
+    // Both pages are read, the all day event is ignored
+    let mut ids = tree.find(window.clone())
+        .map(|k| k.data().to_string())
+        .collect::<Vec<String>>();
+    ids.sort();
+    assert_eq!(ids, vec!["a", "b"]);
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|k| k.contains("timeMin=2018-02-08") && k.contains("timeMax=2018-02-15")));
+    assert!(!requests[0].contains("pageToken"));
+    assert!(requests[1].contains("pageToken=page2"));
+}
+
+#[test]
+fn test_fetch_settings_skips_unshared_calendars() {
+    let (url, server) = fixtures::serve_api_with_status(vec![
+        (
+            "200 OK",
+            r#"{"kind": "calendar#events", "timeZone": "Europe/Paris", "items": []}"#.to_string(),
+        ),
+        (
+            "404 Not Found",
+            r#"{"error": {"code": 404, "message": "Not Found"}}"#.to_string(),
+        ),
+    ]);
+    let mut hub = CalendarHub::new(hyper::Client::new(), NoAuth);
+    hub.base_url(url);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let window = at("2018-02-08T00:00:00Z")..at("2018-02-15T00:00:00Z");
+    let laurent = list_settings_with_api("laurent.charignon@foo.com", &window, &hub);
+    assert_eq!(laurent.map(|k| k.timezone), Some(::chrono_tz::Europe::Paris));
+    assert!(list_settings_with_api("guest@other.com", &window, &hub).is_none());
+    assert!(server.join().unwrap()[0].contains("timeMax=2018-02-15"));
+}
+
+#[test]
+fn test_fetch_timezone_with_freebusy() {
+    let (url, server) = fixtures::serve_api(vec![
+        r#"{"kind": "calendar#events", "timeZone": "Europe/Paris", "items": []}"#.to_string(),
+    ]);
+    let mut hub = CalendarHub::new(hyper::Client::new(), NoAuth);
+    hub.base_url(url);
+    let laurent = fetch_one_timezone_with_api("laurent.charignon@foo.com", &hub);
+    assert_eq!(laurent, Some(::chrono_tz::Europe::Paris));
+    assert!(server.join().unwrap()[0].contains("maxResults=1"));
+}
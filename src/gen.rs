@@ -1,10 +1,11 @@
 use chrono;
-use types::{DesiredMeeting, Meeting};
+use types::{CandidateStats, DesiredMeeting, Meeting};
 use chrono_tz::Tz;
 use chrono::prelude::*;
 use fixtures::test_desired_meeting;
 
 // Generate intervals for a desired date, respecting mint, maxt, step and duration
+// Also returns how many intervals were rejected by reject_datetime_fn
 fn generate_meetings_for_date(
     date: chrono::Date<Tz>,
     mint: chrono::NaiveTime,
@@ -13,8 +14,9 @@ fn generate_meetings_for_date(
     duration: chrono::Duration,
     id: &str,
     reject_datetime_fn: &Fn(chrono::DateTime<Tz>, chrono::DateTime<Tz>) -> bool,
-) -> Vec<Meeting> {
+) -> (Vec<Meeting>, usize) {
     let mut counter = 0;
+    let mut rejected = 0;
     let mut res: Vec<Meeting> = Vec::new();
     let mut t = date.and_time(mint).expect("Cannot parse min date");
     loop {
@@ -27,6 +29,7 @@ fn generate_meetings_for_date(
         t = t + step;
         counter += 1;
         if reject_datetime_fn(start, end) {
+            rejected += 1;
             continue;
         }
         res.push(Meeting {
@@ -35,7 +38,7 @@ fn generate_meetings_for_date(
             end: end.with_timezone(&chrono::Utc),
         })
     }
-    res
+    (res, rejected)
 }
 
 pub fn default_reject_date(date: chrono::Date<Tz>) -> bool {
@@ -53,17 +56,19 @@ pub fn default_reject_datetime(start: chrono::DateTime<Tz>, end: chrono::DateTim
     (start < lunch_end) && (end > lunch_start)
 }
 
-// Generate intervals for a desired meeting
+// Generate intervals for a desired meeting, also counting the slots rejected
+// by the date and datetime rules
 pub fn generate_all_possible_meetings(
     tm: &DesiredMeeting,
     reject_date_fn: &Fn(chrono::Date<Tz>) -> bool,
     reject_datetime_fn: &Fn(chrono::DateTime<Tz>, chrono::DateTime<Tz>) -> bool,
-) -> Vec<Meeting> {
+) -> (Vec<Meeting>, CandidateStats) {
     // Start date to end date, every day
     // Skip Wednesday, Saturday and Sunday
     // Call generate interval for a day
     // From min time, add duration until end time > end hour
     let mut res: Vec<Meeting> = Vec::new();
+    let mut stats = CandidateStats::default();
     let tz = tm.timezone;
     let start_date: chrono::DateTime<Tz> = tm.min_date.with_timezone(&tz);
     let end_date: chrono::DateTime<Tz> = tm.max_date.with_timezone(&tz);
@@ -76,12 +81,24 @@ pub fn generate_all_possible_meetings(
             break;
         }
         if reject_date_fn(date) {
+            let rejected = generate_meetings_for_date(
+                date,
+                mint,
+                maxt,
+                tm.step,
+                tm.duration,
+                "",
+                &|_, _| false,
+            ).0
+                .len();
+            stats.generated += rejected;
+            stats.rejected_by_date += rejected;
             date = date + chrono::Duration::days(1);
             continue;
         }
 
         let ident = format!("{}_{}", tm.slug, id);
-        res.extend(generate_meetings_for_date(
+        let (meetings, rejected) = generate_meetings_for_date(
             date,
             mint,
             maxt,
@@ -89,16 +106,19 @@ pub fn generate_all_possible_meetings(
             tm.duration,
             &ident,
             reject_datetime_fn,
-        ));
+        );
+        stats.generated += meetings.len() + rejected;
+        stats.rejected_by_datetime += rejected;
+        res.extend(meetings);
         date = date + chrono::Duration::days(1);
         id += 1
     }
-    res
+    (res, stats)
 }
 
 #[test]
 fn test_generate_interval() {
-    let intervals = generate_all_possible_meetings(
+    let (intervals, _) = generate_all_possible_meetings(
         &test_desired_meeting(),
         &default_reject_date,
         &default_reject_datetime,
@@ -113,3 +133,17 @@ fn test_generate_interval() {
     // So 20 intervals total
     assert_eq!(intervals.len(), 16);
 }
+
+#[test]
+fn test_generate_interval_stats() {
+    let (_, stats) = generate_all_possible_meetings(
+        &test_desired_meeting(),
+        &default_reject_date,
+        &default_reject_datetime,
+    );
+    // The Saturday has 10 slots from 11 to 4, and the two lunch slots are
+    // rejected on Thursday and Friday
+    assert_eq!(stats.generated, 30);
+    assert_eq!(stats.rejected_by_date, 10);
+    assert_eq!(stats.rejected_by_datetime, 4);
+}
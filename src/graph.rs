@@ -0,0 +1,572 @@
+// Outlook calendars through Microsoft Graph. The meetings are booked in the
+// calendar of one mailbox, which also asks for the schedules of everyone.
+use std::cmp;
+use std::collections::HashMap;
+use std::env;
+use std::io::prelude::*;
+use std::ops::Range;
+use bio::data_structures::interval_tree::IntervalTree;
+use chrono;
+use chrono::prelude::*;
+use hyper;
+use hyper::header::{Authorization, Bearer, Headers};
+use hyper::method::Method;
+use hyper::net::HttpsConnector;
+use hyper_rustls;
+use serde_json;
+use backend::{CalendarBackend, NewEvent};
+use fixtures;
+use ical;
+use types;
+use types::{CalendarSettings, GraphConfig, HourRange, HourSpec, MeetingsTree, Room};
+
+const DEFAULT_BASE_URL: &str = "https://graph.microsoft.com/v1.0/";
+const TOKEN_VARIABLE: &str = "GRAPH_ACCESS_TOKEN";
+// getSchedule answers for at most this many people and days per query
+const SCHEDULE_MAX_PEOPLE: usize = 20;
+const SCHEDULE_MAX_DAYS: i64 = 62;
+const DATE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DateTimeTimeZone {
+    date_time: String,
+    time_zone: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScheduleItem {
+    status: String,
+    start: DateTimeTimeZone,
+    end: DateTimeTimeZone,
+}
+
+#[derive(Debug, Deserialize)]
+struct TimeZoneName {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkingHours {
+    #[serde(default)]
+    days_of_week: Vec<String>,
+    start_time: String,
+    end_time: String,
+    time_zone: TimeZoneName,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScheduleInformation {
+    schedule_id: String,
+    #[serde(default)]
+    schedule_items: Vec<ScheduleItem>,
+    working_hours: Option<WorkingHours>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphRoom {
+    email_address: String,
+    capacity: Option<usize>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    value: Vec<T>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreatedEvent {
+    id: String,
+}
+
+fn to_graph_time(t: &DateTime<chrono::Utc>) -> DateTimeTimeZone {
+    DateTimeTimeZone {
+        date_time: t.format(DATE_TIME_FORMAT).to_string(),
+        time_zone: "UTC".to_string(),
+    }
+}
+
+fn from_graph_time(t: &DateTimeTimeZone) -> DateTime<chrono::Utc> {
+    let tz = ical::to_timezone(&t.time_zone).expect("Cannot decode timezone");
+    let local = chrono::NaiveDateTime::parse_from_str(&t.date_time, "%Y-%m-%dT%H:%M:%S%.f")
+        .expect("Cannot parse date");
+    types::from_local_time(&tz, &local)
+}
+
+fn to_hour_spec(s: &str) -> HourSpec {
+    let t = chrono::NaiveTime::parse_from_str(s, "%H:%M:%S%.f").expect("Cannot parse time");
+    HourSpec {
+        hours: t.hour(),
+        minutes: t.minute(),
+        seconds: t.second(),
+    }
+}
+
+// Free and working elsewhere are not busy. Tentative is, like on google
+// calendar. Outlook does not tell the all day events apart, nor the meetings
+// not answered yet. part tells the schedules of the pieces of a window apart.
+fn add_schedule(tree: &mut MeetingsTree, schedule: &ScheduleInformation, part: usize) {
+    for (k, item) in schedule.schedule_items.iter().enumerate() {
+        if item.status == "free" || item.status == "workingElsewhere" {
+            continue;
+        }
+        tree.insert(
+            from_graph_time(&item.start)..from_graph_time(&item.end),
+            format!("busy_{}_{}", part, k),
+        );
+    }
+}
+
+// The window in pieces short enough for getSchedule
+fn schedule_windows(window: &Range<DateTime<chrono::Utc>>) -> Vec<Range<DateTime<chrono::Utc>>> {
+    let mut res = Vec::new();
+    let mut start = window.start;
+    while start < window.end {
+        let end = cmp::min(start + chrono::Duration::days(SCHEDULE_MAX_DAYS), window.end);
+        res.push(start..end);
+        start = end;
+    }
+    res
+}
+
+fn day_of_week(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "monday",
+        Weekday::Tue => "tuesday",
+        Weekday::Wed => "wednesday",
+        Weekday::Thu => "thursday",
+        Weekday::Fri => "friday",
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+    }
+}
+
+// The ISO weekday of a day of week
+fn from_day_of_week(day: &str) -> u32 {
+    let days = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+    days.iter()
+        .find(|k| day_of_week(**k) == day)
+        .map(|k| k.number_from_monday())
+        .unwrap_or_else(|| panic!("Unknown day of week {}", day))
+}
+
+// The pattern and range of a recurring event from the RRULE we write
+fn to_graph_recurrence(rrule: &str, e: &NewEvent) -> serde_json::Value {
+    let rule = ical::parse_rule(rrule, &e.timezone)
+        .unwrap_or_else(|k| panic!("Cannot read the RRULE {}: {}", rrule, k));
+    let start = e.start.with_timezone(&e.timezone);
+    let days = if rule.by_day.is_empty() {
+        vec![start.weekday()]
+    } else {
+        rule.by_day.iter().map(|k| k.1).collect()
+    };
+    let pattern = match rule.freq.as_str() {
+        "WEEKLY" => json!({
+            "type": "weekly",
+            "interval": rule.interval,
+            "daysOfWeek": days.into_iter().map(day_of_week).collect::<Vec<&str>>(),
+        }),
+        "DAILY" => json!({"type": "daily", "interval": rule.interval}),
+        k => panic!("Unsupported recurrence {}", k),
+    };
+    let start_date = start.format("%Y-%m-%d").to_string();
+    let range = match (rule.count, rule.until) {
+        (Some(count), _) => json!({
+            "type": "numbered",
+            "startDate": start_date,
+            "numberOfOccurrences": count,
+        }),
+        (None, Some(until)) => json!({
+            "type": "endDate",
+            "startDate": start_date,
+            "endDate": until.with_timezone(&e.timezone).format("%Y-%m-%d").to_string(),
+        }),
+        (None, None) => json!({"type": "noEnd", "startDate": start_date}),
+    };
+    json!({"pattern": pattern, "range": range})
+}
+
+// Rooms are invited as resources and are the location of the event.
+// Recurring events are in the local time of their timezone so that they keep
+// it across DST changes.
+fn to_graph_event(e: &NewEvent) -> serde_json::Value {
+    let attendee = |address: &str, kind: &str| {
+        json!({"emailAddress": {"address": address}, "type": kind})
+    };
+    let mut attendees = e.attendees
+        .iter()
+        .map(|k| attendee(k, "required"))
+        .collect::<Vec<serde_json::Value>>();
+    attendees.extend(e.optional_attendees.iter().map(|k| attendee(k, "optional")));
+    attendees.extend(e.room.iter().map(|k| attendee(k, "resource")));
+
+    let time = |t: &DateTime<chrono::Utc>| match e.recurrence {
+        Some(_) => json!({
+            "dateTime": t.with_timezone(&e.timezone).format(DATE_TIME_FORMAT).to_string(),
+            "timeZone": e.timezone.name(),
+        }),
+        None => json!(to_graph_time(t)),
+    };
+    let mut event = json!({
+        "subject": e.title,
+        "body": {"contentType": "text", "content": e.description},
+        "start": time(&e.start),
+        "end": time(&e.end),
+        "attendees": attendees,
+    });
+    if let Some(ref room) = e.room {
+        event["location"] = json!({"displayName": room, "locationEmailAddress": room});
+    }
+    if let Some(ref rrule) = e.recurrence {
+        event["recurrence"] = to_graph_recurrence(rrule, e);
+    }
+    event
+}
+
+pub struct Graph {
+    pub config: GraphConfig,
+}
+
+impl Graph {
+    fn url(&self, path: &str) -> String {
+        let base = self.config
+            .base_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        format!("{}/{}", base.trim_end_matches('/'), path)
+    }
+
+    fn mailbox_url(&self, path: &str) -> String {
+        self.url(&format!("users/{}/{}", self.config.mailbox, path))
+    }
+
+    fn request(&self, method: Method, url: &str, body: Option<&serde_json::Value>) -> String {
+        let client =
+            hyper::Client::with_connector(HttpsConnector::new(hyper_rustls::TlsClient::new()));
+        let token = self.config
+            .access_token
+            .clone()
+            .or_else(|| env::var(TOKEN_VARIABLE).ok())
+            .expect("The graph backend needs an access token");
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer { token }));
+        // All the times we read are in UTC
+        headers.set_raw("Prefer", vec![b"outlook.timezone=\"UTC\"".to_vec()]);
+        headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+        let body = body.map(|k| k.to_string()).unwrap_or_default();
+        let mut response = client
+            .request(method.clone(), url)
+            .headers(headers)
+            .body(body.as_str())
+            .send()
+            .expect("Cannot reach microsoft graph");
+        if !response.status.is_success() {
+            panic!("Microsoft graph refused {} {}: {}", method, url, response.status);
+        }
+        let mut res = String::new();
+        response
+            .read_to_string(&mut res)
+            .expect("Cannot read the microsoft graph answer");
+        res
+    }
+
+    fn get_schedule(
+        &self,
+        people: &[String],
+        window: &Range<DateTime<chrono::Utc>>,
+    ) -> Vec<ScheduleInformation> {
+        println!("Fetching schedules of {:?}", people);
+        let body = json!({
+            "schedules": people,
+            "startTime": to_graph_time(&window.start),
+            "endTime": to_graph_time(&window.end),
+        });
+        let answer = self.request(
+            Method::Post,
+            &self.mailbox_url("calendar/getSchedule"),
+            Some(&body),
+        );
+        let page: Page<ScheduleInformation> =
+            serde_json::from_str(&answer).expect("Cannot decode the schedules");
+        // Like the addresses outside of the organization, taken as free
+        page.value
+            .into_iter()
+            .filter(|s| match s.error {
+                Some(ref error) => {
+                    eprintln!("WARNING: cannot get the schedule of {}: {}", s.schedule_id, error);
+                    false
+                }
+                None => true,
+            })
+            .collect()
+    }
+}
+
+impl CalendarBackend for Graph {
+    fn fetch_availability(
+        &self,
+        people: Vec<String>,
+        window: &Range<DateTime<chrono::Utc>>,
+        _: bool,
+        _: bool,
+    ) -> HashMap<String, MeetingsTree> {
+        let mut res: HashMap<String, MeetingsTree> = HashMap::new();
+        for k in people.chunks(SCHEDULE_MAX_PEOPLE) {
+            for (part, w) in schedule_windows(window).iter().enumerate() {
+                for s in self.get_schedule(k, w) {
+                    let tree = res.entry(s.schedule_id.to_string())
+                        .or_insert_with(IntervalTree::new);
+                    add_schedule(tree, &s, part);
+                }
+            }
+        }
+        // Everyone needs a schedule, even the people graph did not answer for
+        for p in people {
+            res.entry(p).or_insert_with(IntervalTree::new);
+        }
+        res
+    }
+
+    // From the working hours of the schedules of today
+    fn fetch_settings(
+        &self,
+        people: Vec<String>,
+        _: &Range<DateTime<chrono::Utc>>,
+    ) -> HashMap<String, CalendarSettings> {
+        let now = chrono::Utc::now();
+        let window = now..now + chrono::Duration::days(1);
+        people
+            .chunks(SCHEDULE_MAX_PEOPLE)
+            .flat_map(|k| self.get_schedule(k, &window))
+            .filter_map(|k| {
+                let hours = k.working_hours?;
+                let settings = CalendarSettings {
+                    timezone: ical::to_timezone(&hours.time_zone.name)?,
+                    working_hours: Some(HourRange {
+                        from: to_hour_spec(&hours.start_time),
+                        to: to_hour_spec(&hours.end_time),
+                    }),
+                    working_days: hours.days_of_week.iter().map(|d| from_day_of_week(d)).collect(),
+                };
+                Some((k.schedule_id, settings))
+            })
+            .collect()
+    }
+
+    fn create_events(&self, events: &[NewEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|e| {
+                let answer = self.request(
+                    Method::Post,
+                    &self.mailbox_url("events"),
+                    Some(&to_graph_event(e)),
+                );
+                let created: CreatedEvent =
+                    serde_json::from_str(&answer).expect("Cannot decode the created event");
+                created.id
+            })
+            .collect()
+    }
+
+    fn update_event(&self, id: &str, event: &NewEvent) {
+        self.request(
+            Method::Patch,
+            &self.mailbox_url(&format!("events/{}", id)),
+            Some(&to_graph_event(event)),
+        );
+    }
+
+    fn delete_event(&self, id: &str) {
+        self.request(Method::Delete, &self.mailbox_url(&format!("events/{}", id)), None);
+    }
+
+    // The rooms of the organization, following the pages
+    fn list_rooms(&self) -> Vec<Room> {
+        let mut res = Vec::new();
+        let mut url = self.url("places/microsoft.graph.room");
+        loop {
+            let page: Page<GraphRoom> = serde_json::from_str(&self.request(Method::Get, &url, None))
+                .expect("Cannot decode the rooms");
+            res.extend(page.value.into_iter().map(|k| Room {
+                email: k.email_address,
+                capacity: k.capacity,
+                tags: k.tags,
+            }));
+            match page.next_link {
+                Some(ref k) if k.starts_with("http") => url = k.to_string(),
+                Some(ref k) => url = self.url(k.trim_start_matches('/')),
+                None => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_backend(url: &str) -> Graph {
+    Graph {
+        config: GraphConfig {
+            mailbox: "me@foo.com".to_string(),
+            access_token: Some("token".to_string()),
+            base_url: Some(url.to_string()),
+        },
+    }
+}
+
+#[test]
+fn test_graph_fetch_schedules() {
+    let (url, server) = fixtures::serve_api(vec![fixtures::graph_schedules()]);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let window = at("2018-02-08T00:00:00Z")..at("2018-02-15T00:00:00Z");
+    let people = vec![
+        "laurent.charignon@foo.com".to_string(),
+        "contact@laurent.com".to_string(),
+        "guest@other.com".to_string(),
+        "missing@foo.com".to_string(),
+    ];
+    let avail = test_backend(&url).fetch_availability(people, &window, true, true);
+    // Free and working elsewhere do not count
+    let busy = avail["laurent.charignon@foo.com"]
+        .find(window.clone())
+        .map(|k| k.interval().start..k.interval().end)
+        .collect::<Vec<Range<DateTime<chrono::Utc>>>>();
+    assert_eq!(
+        busy,
+        vec![
+            at("2018-02-08T17:00:00Z")..at("2018-02-08T18:00:00Z"),
+            at("2018-02-08T18:00:00Z")..at("2018-02-08T18:30:00Z"),
+        ]
+    );
+    assert_eq!(avail["contact@laurent.com"].find(window.clone()).count(), 0);
+    // Free when graph cannot tell or does not answer
+    assert_eq!(avail["guest@other.com"].find(window.clone()).count(), 0);
+    assert_eq!(avail["missing@foo.com"].find(window.clone()).count(), 0);
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /users/me@foo.com/calendar/getSchedule HTTP/1.1"));
+    assert!(requests[0].contains("Authorization: Bearer token"));
+    assert!(requests[0].contains(
+        r#""startTime":{"dateTime":"2018-02-08T00:00:00","timeZone":"UTC"}"#
+    ));
+}
+
+#[test]
+fn test_graph_fetch_long_windows() {
+    let (url, server) = fixtures::serve_api(vec![fixtures::graph_schedules(); 2]);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let window = at("2018-02-08T00:00:00Z")..at("2018-04-19T00:00:00Z");
+    let people = vec!["laurent.charignon@foo.com".to_string()];
+    test_backend(&url).fetch_availability(people, &window, true, true);
+    // In pieces of at most 62 days
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].contains(
+        r#""endTime":{"dateTime":"2018-04-11T00:00:00","timeZone":"UTC"}"#
+    ));
+    assert!(requests[1].contains(
+        r#""startTime":{"dateTime":"2018-04-11T00:00:00","timeZone":"UTC"}"#
+    ));
+}
+
+#[test]
+fn test_graph_times_in_dst_gaps() {
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let t = DateTimeTimeZone {
+        date_time: "2018-03-11T02:30:00.0000000".to_string(),
+        time_zone: "America/Los_Angeles".to_string(),
+    };
+    assert_eq!(from_graph_time(&t), at("2018-03-11T03:30:00-07:00"));
+}
+
+#[test]
+fn test_graph_working_hours() {
+    let (url, _) = fixtures::serve_api(vec![fixtures::graph_schedules()]);
+    let people = vec!["laurent.charignon@foo.com".to_string()];
+    let now = chrono::Utc::now();
+    let settings = test_backend(&url).fetch_settings(people, &(now..now));
+    let laurent = &settings["laurent.charignon@foo.com"];
+    assert_eq!(laurent.timezone, ::chrono_tz::America::Los_Angeles);
+    assert_eq!(laurent.working_hours.as_ref().map(|k| k.from.hours), Some(8));
+    assert_eq!(laurent.working_hours.as_ref().map(|k| k.to.hours), Some(17));
+    assert_eq!(laurent.working_days, vec![1, 2, 3, 4, 5]);
+    // No working hours for contact
+    assert!(!settings.contains_key("contact@laurent.com"));
+}
+
+#[test]
+fn test_graph_book_with_room() {
+    let (url, server) = fixtures::serve_api(vec![r#"{"id": "AAMkAGI1"}"#.to_string()]);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let event = NewEvent {
+        title: "title".to_string(),
+        description: "description".to_string(),
+        attendees: vec!["laurent.charignon@foo.com".to_string()],
+        optional_attendees: Vec::new(),
+        room: Some("four@jam.com".to_string()),
+        start: at("2018-02-08T09:00:00-08:00"),
+        end: at("2018-02-08T10:00:00-08:00"),
+        timezone: "America/Los_Angeles".parse().unwrap(),
+        recurrence: Some("RRULE:FREQ=WEEKLY;INTERVAL=1;COUNT=4".to_string()),
+    };
+    assert_eq!(test_backend(&url).create_events(&[event]), vec!["AAMkAGI1"]);
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("POST /users/me@foo.com/events HTTP/1.1"));
+    let body = &requests[0][requests[0].find("\r\n\r\n").unwrap()..];
+    let sent: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(sent["attendees"][1]["type"], "resource");
+    assert_eq!(sent["attendees"][1]["emailAddress"]["address"], "four@jam.com");
+    assert_eq!(sent["start"]["dateTime"], "2018-02-08T09:00:00");
+    assert_eq!(sent["start"]["timeZone"], "America/Los_Angeles");
+    assert_eq!(sent["recurrence"]["pattern"]["daysOfWeek"][0], "thursday");
+    assert_eq!(sent["recurrence"]["range"]["numberOfOccurrences"], 4);
+}
+
+#[test]
+fn test_graph_recurrence_until() {
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let event = NewEvent {
+        title: "title".to_string(),
+        description: "description".to_string(),
+        attendees: vec!["laurent.charignon@foo.com".to_string()],
+        optional_attendees: Vec::new(),
+        room: None,
+        start: at("2018-02-08T09:00:00-08:00"),
+        end: at("2018-02-08T10:00:00-08:00"),
+        timezone: "America/Los_Angeles".parse().unwrap(),
+        recurrence: None,
+    };
+    let sent = to_graph_recurrence("RRULE:FREQ=WEEKLY;BYDAY=MO,TH;UNTIL=20180331", &event);
+    assert_eq!(sent["pattern"]["daysOfWeek"], json!(["monday", "thursday"]));
+    assert_eq!(sent["range"]["type"], "endDate");
+    assert_eq!(sent["range"]["endDate"], "2018-03-31");
+}
+
+#[test]
+fn test_graph_list_rooms() {
+    let (url, server) = fixtures::serve_api(fixtures::graph_rooms());
+    let rooms = test_backend(&url).list_rooms();
+    assert_eq!(rooms.len(), 2);
+    assert_eq!(rooms[0].capacity, Some(4));
+    assert_eq!(rooms[1].tags, vec!["video"]);
+    // The second page is read from the link of the first one
+    let requests = server.join().unwrap();
+    assert!(requests[1].starts_with("GET /places/microsoft.graph.room?$skip=1 HTTP/1.1"));
+}
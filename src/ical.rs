@@ -0,0 +1,1060 @@
+// Just enough of iCalendar (RFC 5545) to read the events and busy times of
+// a calendar and to write the events we book
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use chrono;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use backend::NewEvent;
+use types;
+
+// Lines longer than this are folded when writing
+const MAX_LINE_OCTETS: usize = 75;
+
+// Outlook mostly uses windows names for time zones, in its exports too
+const WINDOWS_TIME_ZONES: &[(&str, &str)] = &[
+    ("Pacific Standard Time", "America/Los_Angeles"),
+    ("Mountain Standard Time", "America/Denver"),
+    ("Central Standard Time", "America/Chicago"),
+    ("Eastern Standard Time", "America/New_York"),
+    ("GMT Standard Time", "Europe/London"),
+    ("Romance Standard Time", "Europe/Paris"),
+    ("W. Europe Standard Time", "Europe/Berlin"),
+    ("India Standard Time", "Asia/Kolkata"),
+    ("China Standard Time", "Asia/Shanghai"),
+    ("Tokyo Standard Time", "Asia/Tokyo"),
+    ("AUS Eastern Standard Time", "Australia/Sydney"),
+    ("UTC", "UTC"),
+];
+
+// An IANA or windows timezone name
+pub fn to_timezone(name: &str) -> Option<Tz> {
+    name.parse().ok().or_else(|| {
+        WINDOWS_TIME_ZONES
+            .iter()
+            .find(|k| k.0 == name)
+            .and_then(|k| k.1.parse().ok())
+    })
+}
+
+// Also the names behind a prefix, like /mozilla.org/20050126_1/Europe/Paris
+fn named_timezone(name: &str) -> Option<Tz> {
+    to_timezone(name).or_else(|| {
+        name.match_indices('/')
+            .filter_map(|(k, _)| to_timezone(&name[k + 1..]))
+            .next()
+    })
+}
+
+// The timezone a VTIMEZONE stands for, from its TZID or its X-LIC-LOCATION
+pub fn vtimezone_timezone(c: &Component) -> Option<Tz> {
+    c.property("TZID")
+        .and_then(|k| named_timezone(&k.value))
+        .or_else(|| c.property("X-LIC-LOCATION").and_then(|k| named_timezone(&k.value)))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Property {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|k| k.0.eq_ignore_ascii_case(name))
+            .map(|k| k.1.as_str())
+    }
+
+    // A DATE or DATE-TIME value. Dates are the start of the day and floating
+    // times are in tz.
+    pub fn datetime(&self, tz: &Tz) -> DateTime<chrono::Utc> {
+        parse_datetime(&self.value, &self.timezone(tz))
+    }
+
+    // The timezone a value is written in, tz for floating times
+    pub fn timezone(&self, tz: &Tz) -> Tz {
+        match self.param("TZID") {
+            _ if self.value.ends_with('Z') => Tz::UTC,
+            Some(k) => to_timezone(k).unwrap_or(*tz),
+            None => *tz,
+        }
+    }
+
+    pub fn is_date(&self) -> bool {
+        self.param("VALUE") == Some("DATE")
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|k| k.name == name)
+    }
+
+    pub fn properties_named<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a Property> + 'a {
+        self.properties.iter().filter(move |k| k.name == name)
+    }
+
+    // The sub components with this name, at any depth
+    pub fn find_all(&self, name: &str) -> Vec<&Component> {
+        let mut res = Vec::new();
+        for c in &self.components {
+            if c.name == name {
+                res.push(c);
+            }
+            res.extend(c.find_all(name));
+        }
+        res
+    }
+
+    // Start and end of an event, from DTEND or DURATION. An event with
+    // neither lasts a day if it starts on a date and no time otherwise.
+    pub fn event_bounds(&self, tz: &Tz) -> (DateTime<chrono::Utc>, DateTime<chrono::Utc>) {
+        let dtstart = self.property("DTSTART").expect("An event needs a start");
+        let start = dtstart.datetime(tz);
+        let end = match (self.property("DTEND"), self.property("DURATION")) {
+            (Some(k), _) => k.datetime(tz),
+            (None, Some(k)) => start + parse_duration(&k.value),
+            (None, None) if dtstart.is_date() => start + chrono::Duration::days(1),
+            (None, None) => start,
+        };
+        (start, end)
+    }
+}
+
+// The supported subset of RRULE
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+    pub freq: String,
+    pub interval: i64,
+    pub count: Option<usize>,
+    // Last time an occurrence can start
+    pub until: Option<DateTime<chrono::Utc>>,
+    // Days of the week, with their rank in the month for MONTHLY like 2TU
+    pub by_day: Vec<(Option<i64>, Weekday)>,
+    // Negative days count from the end of the month
+    pub by_month_day: Vec<i64>,
+    pub by_month: Vec<u32>,
+    // Which of the days of every period, negative ones count from the end
+    pub by_set_pos: Vec<i64>,
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    match s {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+impl Rule {
+    fn daily() -> Rule {
+        Rule {
+            freq: "DAILY".to_string(),
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+        }
+    }
+}
+
+// The rule, or what is not supported in it. The value can start with RRULE:
+// and floating UNTIL times are in tz.
+pub fn parse_rule(value: &str, tz: &Tz) -> Result<Rule, String> {
+    let mut rule = Rule {
+        freq: String::new(),
+        ..Rule::daily()
+    };
+    for part in value.trim_start_matches("RRULE:").split(';') {
+        let mut kv = part.splitn(2, '=');
+        let (key, value) = (kv.next().unwrap_or_default(), kv.next().unwrap_or_default());
+        let invalid = || format!("invalid RRULE part {}", part);
+        let numbers = || {
+            value
+                .split(',')
+                .map(|k| k.trim_start_matches('+').parse::<i64>().ok().filter(|k| *k != 0))
+                .collect::<Option<Vec<i64>>>()
+                .ok_or_else(invalid)
+        };
+        match key {
+            "FREQ" => match value {
+                "DAILY" | "WEEKLY" | "MONTHLY" | "YEARLY" => rule.freq = value.to_string(),
+                _ => return Err(format!("unsupported RRULE FREQ {}", value)),
+            },
+            "INTERVAL" => rule.interval = value.parse().map_err(|_| invalid())?,
+            "COUNT" => rule.count = Some(value.parse().map_err(|_| invalid())?),
+            // A date includes the whole day
+            "UNTIL" if value.contains('T') => rule.until = Some(parse_datetime(value, tz)),
+            "UNTIL" => {
+                rule.until = Some(
+                    parse_datetime(value, tz) + chrono::Duration::days(1)
+                        - chrono::Duration::seconds(1),
+                )
+            }
+            "BYDAY" => {
+                for d in value.split(',') {
+                    let (rank, day) = d.split_at(d.len().saturating_sub(2));
+                    let rank = match rank.trim_start_matches('+') {
+                        "" => None,
+                        k => Some(k.parse().map_err(|_| invalid())?),
+                    };
+                    rule.by_day.push((rank, parse_weekday(day).ok_or_else(invalid)?));
+                }
+            }
+            "BYMONTHDAY" => rule.by_month_day = numbers()?,
+            "BYMONTH" => {
+                rule.by_month = numbers()?
+                    .into_iter()
+                    .map(|k| if 0 < k && k <= 12 { Ok(k as u32) } else { Err(invalid()) })
+                    .collect::<Result<Vec<u32>, String>>()?
+            }
+            "BYSETPOS" => rule.by_set_pos = numbers()?,
+            "WKST" => (),
+            _ => return Err(format!("unsupported RRULE part {}", part)),
+        }
+    }
+    if rule.freq.is_empty() {
+        return Err("RRULE without FREQ".to_string());
+    }
+    if rule.interval < 1 {
+        return Err("the RRULE INTERVAL must be at least 1".to_string());
+    }
+    // The ranks would be in the year
+    if rule.freq == "YEARLY" && rule.by_month.is_empty() && !rule.by_day.is_empty() {
+        return Err("unsupported yearly RRULE BYDAY without BYMONTH".to_string());
+    }
+    Ok(rule)
+}
+
+// The days of a month with this week day, the nth only if ranked (-1 is
+// the last one)
+fn days_in_month(year: i32, month: u32, rank: Option<i64>, day: Weekday) -> Vec<NaiveDate> {
+    let days = (1..32)
+        .filter_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+        .filter(|d| d.weekday() == day)
+        .collect::<Vec<NaiveDate>>();
+    match rank {
+        None => days,
+        Some(k) if k > 0 => days.get(k as usize - 1).cloned().into_iter().collect(),
+        Some(k) => days.len()
+            .checked_sub(-k as usize)
+            .map(|i| days[i])
+            .into_iter()
+            .collect(),
+    }
+}
+
+// The kth day of a month, -1 is the last one
+fn month_day(year: i32, month: u32, k: i64) -> Option<NaiveDate> {
+    if k > 0 {
+        return NaiveDate::from_ymd_opt(year, month, k as u32);
+    }
+    let next = if month == 12 {
+        NaiveDate::from_ymd(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(year, month + 1, 1)
+    };
+    Some(next + chrono::Duration::days(k)).filter(|d| d.month() == month)
+}
+
+// The days of a month matching a MONTHLY or YEARLY rule, on the day of the
+// first occurrence if the rule does not tell
+fn month_days(rule: &Rule, year: i32, month: u32, day: u32) -> Vec<NaiveDate> {
+    let by_month_day = rule.by_month_day
+        .iter()
+        .filter_map(|&k| month_day(year, month, k))
+        .collect::<Vec<NaiveDate>>();
+    if !rule.by_day.is_empty() {
+        rule.by_day
+            .iter()
+            .flat_map(|&(rank, day)| days_in_month(year, month, rank, day))
+            .filter(|d| rule.by_month_day.is_empty() || by_month_day.contains(d))
+            .collect()
+    } else if !rule.by_month_day.is_empty() {
+        by_month_day
+    } else {
+        NaiveDate::from_ymd_opt(year, month, day).into_iter().collect()
+    }
+}
+
+// The first day of the nth period of a rule and the days of the period
+// matching it, in order
+fn period_days(rule: &Rule, start: NaiveDate, n: i64) -> (NaiveDate, Vec<NaiveDate>) {
+    let n = n * rule.interval;
+    let (first, mut days) = match rule.freq.as_str() {
+        "DAILY" => {
+            let day = start + chrono::Duration::days(n);
+            (day, vec![day])
+        }
+        "WEEKLY" => {
+            let monday = start
+                - chrono::Duration::days(start.weekday().num_days_from_monday() as i64)
+                + chrono::Duration::weeks(n);
+            let mut days = if rule.by_day.is_empty() {
+                vec![start.weekday()]
+            } else {
+                rule.by_day.iter().map(|k| k.1).collect()
+            };
+            days.sort_by_key(|k| k.num_days_from_monday());
+            let days = days.iter()
+                .map(|k| monday + chrono::Duration::days(k.num_days_from_monday() as i64))
+                .collect();
+            (monday, days)
+        }
+        "MONTHLY" => {
+            let months = start.year() as i64 * 12 + start.month0() as i64 + n;
+            let (year, month) = ((months / 12) as i32, (months % 12) as u32 + 1);
+            let days = month_days(rule, year, month, start.day());
+            (NaiveDate::from_ymd(year, month, 1), days)
+        }
+        "YEARLY" => {
+            let year = start.year() + n as i32;
+            // Every month for the days of the month alone
+            let months = if !rule.by_month.is_empty() {
+                rule.by_month.clone()
+            } else if !rule.by_month_day.is_empty() {
+                (1..13).collect()
+            } else {
+                vec![start.month()]
+            };
+            let days = months
+                .iter()
+                .flat_map(|&k| month_days(rule, year, k, start.day()))
+                .collect();
+            (NaiveDate::from_ymd(year, 1, 1), days)
+        }
+        k => panic!("Unsupported RRULE FREQ {}", k),
+    };
+    if !rule.by_month.is_empty() {
+        days.retain(|d| rule.by_month.contains(&d.month()));
+    }
+    days.sort();
+    days.dedup();
+    if !rule.by_set_pos.is_empty() {
+        let len = days.len() as i64;
+        let mut picked = rule.by_set_pos
+            .iter()
+            .map(|&k| if k > 0 { k - 1 } else { len + k })
+            .filter(|&k| 0 <= k && k < len)
+            .map(|k| days[k as usize])
+            .collect::<Vec<NaiveDate>>();
+        picked.sort();
+        picked.dedup();
+        days = picked;
+    }
+    (first, days)
+}
+
+// The start of the occurrences of a rule starting at or before last.
+// Occurrences keep their local time in tz across DST changes.
+fn rule_starts(
+    rule: &Rule,
+    start: &DateTime<chrono::Utc>,
+    tz: &Tz,
+    last: &DateTime<chrono::Utc>,
+) -> Vec<DateTime<chrono::Utc>> {
+    let local = start.with_timezone(tz).naive_local();
+    let mut res = Vec::new();
+    let mut seen = 0;
+    for n in 0.. {
+        let (first, days) = period_days(rule, local.date(), n);
+        if first.and_time(local.time()) > last.with_timezone(tz).naive_local() {
+            return res;
+        }
+        for day in days {
+            // Shifted forward when it falls in a DST gap
+            let t = types::from_local_time(tz, &day.and_time(local.time()));
+            if t < *start {
+                continue;
+            }
+            if t > *last || rule.until.is_some_and(|k| t > k) || rule.count == Some(seen) {
+                return res;
+            }
+            seen += 1;
+            res.push(t);
+        }
+    }
+    res
+}
+
+// Join the folded lines back
+fn unfold(s: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    for line in s.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = res.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            res.push(line.to_string());
+        }
+    }
+    res
+}
+
+fn parse_property(line: &str) -> Property {
+    // The value starts at the first colon outside of a quoted parameter
+    let mut quoted = false;
+    let mut colon = line.len();
+    for (k, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => {
+                colon = k;
+                break;
+            }
+            _ => (),
+        }
+    }
+    let value = line.get(colon + 1..).unwrap_or_default().to_string();
+    let mut parts = line[..colon].split(';');
+    let name = parts.next().unwrap_or_default().to_uppercase();
+    let params = parts
+        .map(|p| {
+            let mut kv = p.splitn(2, '=');
+            let key = kv.next().unwrap_or_default().to_uppercase();
+            let value = kv.next().unwrap_or_default().trim_matches('"').to_string();
+            (key, value)
+        })
+        .collect();
+    Property {
+        name,
+        params,
+        value,
+    }
+}
+
+// Is the event making person busy? Events of people who did not answer are
+// ignored like on google calendar, but people not listed as attendees are
+// the organizers of the event.
+pub fn blocks(
+    event: &Component,
+    person: &str,
+    ignore_all_day_events: bool,
+    ignore_meetings_with_no_response: bool,
+) -> bool {
+    let value = |name: &str| event.property(name).map(|k| k.value.to_uppercase());
+    if value("STATUS") == Some("CANCELLED".to_string())
+        || value("TRANSP") == Some("TRANSPARENT".to_string())
+    {
+        return false;
+    }
+    if ignore_all_day_events && event.property("DTSTART").is_some_and(|k| k.is_date()) {
+        return false;
+    }
+    if !ignore_meetings_with_no_response {
+        return true;
+    }
+    let mailto = format!("mailto:{}", person);
+    match event
+        .properties_named("ATTENDEE")
+        .find(|k| k.value.eq_ignore_ascii_case(&mailto))
+    {
+        Some(a) => matches!(a.param("PARTSTAT"), Some("ACCEPTED") | Some("TENTATIVE")),
+        None => true,
+    }
+}
+
+// The start and end of every occurrence of the events starting at or before
+// last. Recurring events follow their RRULE without their EXDATE, an event
+// with a RECURRENCE-ID replaces that occurrence of the event sharing its UID.
+// Floating times and dates are in tz. Rather than missing some occurrences,
+// the events with a rule we cannot follow take their time of every day, or
+// the whole time from their start when they repeat within a day.
+pub fn expand<'a>(
+    events: &[&'a Component],
+    tz: &Tz,
+    last: &DateTime<chrono::Utc>,
+) -> Vec<(&'a Component, DateTime<chrono::Utc>, DateTime<chrono::Utc>)> {
+    let uid = |e: &Component| e.property("UID").map(|k| k.value.to_string());
+    let mut moved: HashMap<Option<String>, HashSet<DateTime<chrono::Utc>>> = HashMap::new();
+    for e in events {
+        if let Some(k) = e.property("RECURRENCE-ID") {
+            moved.entry(uid(e)).or_default().insert(k.datetime(tz));
+        }
+    }
+    let mut res = Vec::new();
+    for &e in events {
+        let (start, end) = e.event_bounds(tz);
+        let rrule = match e.property("RRULE") {
+            Some(k) if e.property("RECURRENCE-ID").is_none() => k,
+            _ => {
+                res.push((e, start, end));
+                continue;
+            }
+        };
+        let local_tz = e.property("DTSTART")
+            .map(|k| k.timezone(tz))
+            .unwrap_or(*tz);
+        let mut excluded = moved.get(&uid(e)).cloned().unwrap_or_default();
+        for p in e.properties_named("EXDATE") {
+            let local_tz = p.timezone(tz);
+            for value in p.value.split(',') {
+                excluded.insert(parse_datetime(value, &local_tz));
+            }
+        }
+        let rule = match parse_rule(&rrule.value, &local_tz) {
+            Ok(k) => k,
+            Err(k) => {
+                let within_a_day = ["HOURLY", "MINUTELY", "SECONDLY"]
+                    .iter()
+                    .any(|f| rrule.value.split(';').any(|p| p == format!("FREQ={}", f)));
+                eprintln!(
+                    "WARNING: {} in the event {}, taken as busy {}",
+                    k,
+                    uid(e).unwrap_or_default(),
+                    if within_a_day { "from its start on" } else { "at its time every day" }
+                );
+                if within_a_day {
+                    res.push((e, start, cmp::max(end, *last)));
+                    continue;
+                }
+                Rule::daily()
+            }
+        };
+        for t in rule_starts(&rule, &start, &local_tz, last) {
+            if !excluded.contains(&t) {
+                res.push((e, t, t + end.signed_duration_since(start)));
+            }
+        }
+    }
+    res
+}
+
+// Parse an iCalendar stream, returns a root component named after nothing
+// holding everything found (usually one VCALENDAR)
+pub fn parse(s: &str) -> Component {
+    let mut stack = vec![Component {
+        name: String::new(),
+        properties: Vec::new(),
+        components: Vec::new(),
+    }];
+    for line in unfold(s) {
+        let p = parse_property(&line);
+        match p.name.as_str() {
+            "BEGIN" => stack.push(Component {
+                name: p.value.to_uppercase(),
+                properties: Vec::new(),
+                components: Vec::new(),
+            }),
+            "END" if stack.len() > 1 => {
+                let c = stack.pop().unwrap();
+                stack.last_mut().unwrap().components.push(c);
+            }
+            "END" => panic!("Malformed iCalendar: unexpected {}", line),
+            _ => stack.last_mut().unwrap().properties.push(p),
+        }
+    }
+    if stack.len() != 1 {
+        panic!("Malformed iCalendar: {} is not closed", stack.last().unwrap().name);
+    }
+    let mut root = stack.pop().unwrap();
+    resolve_timezones(&mut root);
+    root
+}
+
+fn for_each_property<F: FnMut(&mut Property)>(c: &mut Component, f: &mut F) {
+    for p in &mut c.properties {
+        f(p);
+    }
+    for k in &mut c.components {
+        for_each_property(k, f);
+    }
+}
+
+// Rewrite the TZID parameters with IANA names, from the name itself or from
+// its VTIMEZONE. Unknown timezones are an error, reading their times as
+// floating could be hours off.
+fn resolve_timezones(root: &mut Component) {
+    let mut names = HashSet::new();
+    for_each_property(root, &mut |p| {
+        names.extend(p.params.iter().filter(|k| k.0 == "TZID").map(|k| k.1.to_string()))
+    });
+    let resolved = names
+        .into_iter()
+        .map(|name| {
+            let tz = named_timezone(&name)
+                .or_else(|| {
+                    root.find_all("VTIMEZONE")
+                        .into_iter()
+                        .filter(|c| c.property("TZID").is_some_and(|k| k.value == name))
+                        .filter_map(vtimezone_timezone)
+                        .next()
+                })
+                .unwrap_or_else(|| panic!("Unknown timezone {} in the calendar", name));
+            (name, tz)
+        })
+        .collect::<HashMap<String, Tz>>();
+    for_each_property(root, &mut |p| {
+        for k in p.params.iter_mut().filter(|k| k.0 == "TZID") {
+            k.1 = resolved[&k.1].name().to_string();
+        }
+    });
+}
+
+// 20180208T090000Z, 20180208T090000 in tz or 20180208 at midnight in tz
+pub fn parse_datetime(value: &str, tz: &Tz) -> DateTime<chrono::Utc> {
+    if value.ends_with('Z') {
+        return chrono::Utc
+            .datetime_from_str(value, "%Y%m%dT%H%M%SZ")
+            .expect("Cannot parse date");
+    }
+    let local = if value.contains('T') {
+        chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").expect("Cannot parse date")
+    } else {
+        chrono::NaiveDate::parse_from_str(value, "%Y%m%d")
+            .expect("Cannot parse date")
+            .and_hms(0, 0, 0)
+    };
+    types::from_local_time(tz, &local)
+}
+
+// Durations like P1D, PT1H30M or -PT15M
+pub fn parse_duration(s: &str) -> chrono::Duration {
+    let (sign, s) = match s.chars().next() {
+        Some('-') => (-1, &s[1..]),
+        Some('+') => (1, &s[1..]),
+        _ => (1, s),
+    };
+    let mut res = chrono::Duration::zero();
+    let mut number = String::new();
+    for c in s.trim_start_matches('P').chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => (),
+            _ => {
+                let n = number.parse::<i64>().expect("Cannot parse duration");
+                number.clear();
+                res = res + match c {
+                    'W' => chrono::Duration::weeks(n),
+                    'D' => chrono::Duration::days(n),
+                    'H' => chrono::Duration::hours(n),
+                    'M' => chrono::Duration::minutes(n),
+                    'S' => chrono::Duration::seconds(n),
+                    _ => panic!("Cannot parse duration {}", s),
+                };
+            }
+        }
+    }
+    res * sign
+}
+
+// A uid for the kth event written at now
+pub fn new_uid(now: &DateTime<chrono::Utc>, k: usize) -> String {
+    format!(
+        "{}{:09}-{}@optirust",
+        now.timestamp(),
+        now.timestamp_subsec_nanos(),
+        k
+    )
+}
+
+pub fn format_utc(t: &DateTime<chrono::Utc>) -> String {
+    t.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Write one content line, folded every 75 octets
+fn write_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE_OCTETS {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+// The VEVENT of an event to book. Recurring events are written in the local
+// time of their timezone so that they keep it across DST changes.
+pub fn write_event(
+    out: &mut String,
+    e: &NewEvent,
+    uid: &str,
+    organizer: Option<&str>,
+    stamp: &DateTime<chrono::Utc>,
+) {
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}", uid),
+        format!("DTSTAMP:{}", format_utc(stamp)),
+    ];
+    if let Some(organizer) = organizer {
+        lines.push(format!("ORGANIZER:mailto:{}", organizer));
+    }
+    match e.recurrence {
+        Some(ref rrule) => {
+            let local = |t: &DateTime<chrono::Utc>| {
+                t.with_timezone(&e.timezone).format("%Y%m%dT%H%M%S").to_string()
+            };
+            lines.push(format!("DTSTART;TZID={}:{}", e.timezone.name(), local(&e.start)));
+            lines.push(format!("DTEND;TZID={}:{}", e.timezone.name(), local(&e.end)));
+            lines.push(rrule.to_string());
+        }
+        None => {
+            lines.push(format!("DTSTART:{}", format_utc(&e.start)));
+            lines.push(format!("DTEND:{}", format_utc(&e.end)));
+        }
+    }
+    lines.push(format!("SUMMARY:{}", escape(&e.title)));
+    lines.push(format!("DESCRIPTION:{}", escape(&e.description)));
+    for a in &e.attendees {
+        lines.push(format!("ATTENDEE;ROLE=REQ-PARTICIPANT;PARTSTAT=NEEDS-ACTION:mailto:{}", a));
+    }
+    for a in &e.optional_attendees {
+        lines.push(format!("ATTENDEE;ROLE=OPT-PARTICIPANT;PARTSTAT=NEEDS-ACTION:mailto:{}", a));
+    }
+    if let Some(ref room) = e.room {
+        lines.push(format!("LOCATION:{}", escape(room)));
+        lines.push(format!("ATTENDEE;CUTYPE=ROOM;PARTSTAT=NEEDS-ACTION:mailto:{}", room));
+    }
+    lines.push("END:VEVENT".to_string());
+    for l in lines {
+        write_line(out, &l);
+    }
+}
+
+fn write_calendar(
+    events: &[(String, &NewEvent)],
+    method: Option<&str>,
+    organizer: Option<&str>,
+    stamp: &DateTime<chrono::Utc>,
+) -> String {
+    let mut out = String::new();
+    write_line(&mut out, "BEGIN:VCALENDAR");
+    write_line(&mut out, "VERSION:2.0");
+    write_line(&mut out, "PRODID:-//optirust//optirust//EN");
+    if let Some(method) = method {
+        write_line(&mut out, &format!("METHOD:{}", method));
+    }
+    for &(ref uid, e) in events {
+        write_event(&mut out, e, uid, organizer, stamp);
+    }
+    write_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+// A whole calendar with the events, given with their uid, to store on a
+// server that sends the invites of the organizer
+pub fn calendar(
+    events: &[(String, &NewEvent)],
+    organizer: &str,
+    stamp: &DateTime<chrono::Utc>,
+) -> String {
+    write_calendar(events, None, Some(organizer), stamp)
+}
+
+// The events as invites from the organizer, to mail or import in any client.
+// A REQUEST needs an organizer, without one the events are only published.
+pub fn invites(
+    events: &[(String, &NewEvent)],
+    organizer: Option<&str>,
+    stamp: &DateTime<chrono::Utc>,
+) -> String {
+    let method = if organizer.is_some() { "REQUEST" } else { "PUBLISH" };
+    write_calendar(events, Some(method), organizer, stamp)
+}
+
+#[test]
+fn test_parse_events() {
+    let root = parse(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:a\r
+DTSTART;TZID=America/Los_Angeles:20180208T090000\r
+DURATION:PT1H30M\r
+SUMMARY:a very long title that goes on and on and on until it has to be fol\r
+ ded\r
+ATTENDEE;CN=\"Charignon: Laurent\";PARTSTAT=ACCEPTED:mailto:laurent.charignon@foo.com\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:b\r
+DTSTART;VALUE=DATE:20180209\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+    );
+    let events = root.find_all("VEVENT");
+    assert_eq!(events.len(), 2);
+    let utc: Tz = "UTC".parse().unwrap();
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    assert_eq!(
+        events[0].event_bounds(&utc),
+        (at("2018-02-08T09:00:00-08:00"), at("2018-02-08T10:30:00-08:00"))
+    );
+    assert!(events[0].property("SUMMARY").unwrap().value.ends_with("folded"));
+    let attendee = events[0].property("ATTENDEE").unwrap();
+    assert_eq!(attendee.param("partstat"), Some("ACCEPTED"));
+    assert_eq!(attendee.param("CN"), Some("Charignon: Laurent"));
+    assert_eq!(attendee.value, "mailto:laurent.charignon@foo.com");
+    assert_eq!(
+        events[1].event_bounds(&utc),
+        (at("2018-02-09T00:00:00Z"), at("2018-02-10T00:00:00Z"))
+    );
+    assert_eq!(parse_duration("-P1DT2H"), -chrono::Duration::hours(26));
+    // Times skipped by a DST change are shifted forward by the length of the gap
+    let la: Tz = "America/Los_Angeles".parse().unwrap();
+    assert_eq!(parse_datetime("20180311T023000", &la), at("2018-03-11T03:30:00-07:00"));
+}
+
+#[test]
+fn test_write_event() {
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let event = NewEvent {
+        title: "sync, weekly".to_string(),
+        description: "line one\nline two".to_string(),
+        attendees: vec!["laurent.charignon@foo.com".to_string()],
+        optional_attendees: Vec::new(),
+        room: Some("four@jam.com".to_string()),
+        start: at("2018-02-08T09:00:00-08:00"),
+        end: at("2018-02-08T10:00:00-08:00"),
+        timezone: "America/Los_Angeles".parse().unwrap(),
+        recurrence: Some("RRULE:FREQ=WEEKLY;INTERVAL=1;COUNT=4".to_string()),
+    };
+    let s = calendar(
+        &[("uid1".to_string(), &event)],
+        "me@foo.com",
+        &at("2018-02-01T00:00:00Z"),
+    );
+    assert!(s.lines().all(|k| k.len() <= MAX_LINE_OCTETS + 1));
+    assert!(s.contains("DTSTART;TZID=America/Los_Angeles:20180208T090000\r\n"));
+    assert!(s.contains("SUMMARY:sync\\, weekly\r\n"));
+    assert!(s.contains("DESCRIPTION:line one\\nline two\r\n"));
+    assert!(s.contains("ORGANIZER:mailto:me@foo.com\r\n"));
+
+    // What we write we read back
+    let root = parse(&s);
+    let events = root.find_all("VEVENT");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_bounds(&"UTC".parse().unwrap()), (event.start, event.end));
+    assert_eq!(events[0].properties_named("ATTENDEE").count(), 2);
+    assert_eq!(events[0].property("RRULE").unwrap().value, "FREQ=WEEKLY;INTERVAL=1;COUNT=4");
+}
+
+#[test]
+fn test_expand_recurrences() {
+    let root = parse(
+        "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+UID:weekly\r
+DTSTART;TZID=America/Los_Angeles:20180308T090000\r
+DTEND;TZID=America/Los_Angeles:20180308T100000\r
+RRULE:FREQ=WEEKLY;BYDAY=TU,TH;COUNT=5\r
+EXDATE;TZID=America/Los_Angeles:20180313T090000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:weekly\r
+RECURRENCE-ID;TZID=America/Los_Angeles:20180315T090000\r
+DTSTART;TZID=America/Los_Angeles:20180315T140000\r
+DTEND;TZID=America/Los_Angeles:20180315T150000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:monthly\r
+DTSTART:20180131T170000Z\r
+DURATION:PT30M\r
+RRULE:FREQ=MONTHLY;BYDAY=-1WE;UNTIL=20180430\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:daily\r
+DTSTART;VALUE=DATE:20180301\r
+RRULE:FREQ=DAILY;INTERVAL=2\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+    );
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let starts = |uid: &str| {
+        let mut res = expand(&root.find_all("VEVENT"), &Tz::UTC, &at("2018-05-01T00:00:00Z"))
+            .into_iter()
+            .filter(|k| k.0.property("UID").unwrap().value == uid)
+            .map(|k| k.1)
+            .collect::<Vec<DateTime<chrono::Utc>>>();
+        res.sort();
+        res
+    };
+    // 9am across DST, without the excluded tuesday and with the moved
+    // thursday. The excluded occurrence counts.
+    assert_eq!(
+        starts("weekly"),
+        vec![
+            at("2018-03-08T09:00:00-08:00"),
+            at("2018-03-15T14:00:00-07:00"),
+            at("2018-03-20T09:00:00-07:00"),
+            at("2018-03-22T09:00:00-07:00"),
+        ]
+    );
+    // The last wednesday of january, february, march and april
+    assert_eq!(
+        starts("monthly"),
+        vec![
+            at("2018-01-31T17:00:00Z"),
+            at("2018-02-28T17:00:00Z"),
+            at("2018-03-28T17:00:00Z"),
+            at("2018-04-25T17:00:00Z"),
+        ]
+    );
+    // Never ending, up to the bound
+    let daily = starts("daily");
+    assert_eq!(daily.len(), 31);
+    assert_eq!(daily[1], at("2018-03-03T00:00:00Z"));
+}
+
+#[test]
+fn test_expand_real_exports() {
+    let root = parse(
+        "BEGIN:VCALENDAR\r
+BEGIN:VTIMEZONE\r
+TZID:(UTC+01:00) Brussels, Copenhagen, Madrid, Paris\r
+X-LIC-LOCATION:Europe/Paris\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:last-weekday\r
+DTSTART:20180131T170000Z\r
+DURATION:PT30M\r
+RRULE:FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1;COUNT=3\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:end-of-month\r
+DTSTART:20180131T170000Z\r
+DURATION:PT30M\r
+RRULE:FREQ=MONTHLY;BYMONTHDAY=-1;COUNT=3\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:dst\r
+DTSTART;TZID=America/Los_Angeles:20180304T090000\r
+DURATION:PT30M\r
+RRULE:FREQ=YEARLY;BYMONTH=3,11;BYDAY=1SU\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:hourly\r
+DTSTART:20180208T090000Z\r
+DURATION:PT30M\r
+RRULE:FREQ=HOURLY;COUNT=5\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:windows\r
+DTSTART;TZID=\"Pacific Standard Time\":20180208T090000\r
+DURATION:PT30M\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:vtimezone\r
+DTSTART;TZID=\"(UTC+01:00) Brussels, Copenhagen, Madrid, Paris\":20180208T090000\r
+DURATION:PT30M\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:prefixed\r
+DTSTART;TZID=/mozilla.org/20050126_1/Europe/Paris:20180208T090000\r
+DURATION:PT30M\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:bysecond\r
+DTSTART;TZID=America/Los_Angeles:20180208T090000\r
+DURATION:PT30M\r
+RRULE:FREQ=DAILY;BYSECOND=10;COUNT=2\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:gap\r
+DTSTART;TZID=America/Los_Angeles:20180310T023000\r
+DURATION:PT30M\r
+RRULE:FREQ=DAILY;COUNT=2\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+    );
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let last = at("2019-01-01T00:00:00Z");
+    let occurrences = |uid: &str| {
+        let mut res = expand(&root.find_all("VEVENT"), &Tz::UTC, &last)
+            .into_iter()
+            .filter(|k| k.0.property("UID").unwrap().value == uid)
+            .map(|k| (k.1, k.2))
+            .collect::<Vec<(DateTime<chrono::Utc>, DateTime<chrono::Utc>)>>();
+        res.sort();
+        res
+    };
+    let starts = |uid: &str| occurrences(uid).into_iter().map(|k| k.0).collect::<Vec<_>>();
+    // Friday the 30th of march is the last weekday
+    assert_eq!(
+        starts("last-weekday"),
+        vec![
+            at("2018-01-31T17:00:00Z"),
+            at("2018-02-28T17:00:00Z"),
+            at("2018-03-30T17:00:00Z"),
+        ]
+    );
+    assert_eq!(
+        starts("end-of-month"),
+        vec![
+            at("2018-01-31T17:00:00Z"),
+            at("2018-02-28T17:00:00Z"),
+            at("2018-03-31T17:00:00Z"),
+        ]
+    );
+    assert_eq!(
+        starts("dst"),
+        vec![at("2018-03-04T09:00:00-08:00"), at("2018-11-04T09:00:00-08:00")]
+    );
+    assert_eq!(starts("windows"), vec![at("2018-02-08T09:00:00-08:00")]);
+    assert_eq!(starts("vtimezone"), vec![at("2018-02-08T09:00:00+01:00")]);
+    assert_eq!(starts("prefixed"), vec![at("2018-02-08T09:00:00+01:00")]);
+    // Unsupported rules are busy from their start within a day, at their time
+    // every day otherwise
+    assert_eq!(occurrences("hourly"), vec![(at("2018-02-08T09:00:00Z"), last)]);
+    let bysecond = starts("bysecond");
+    assert_eq!(bysecond.len(), 327);
+    assert_eq!(bysecond[326], at("2018-12-31T09:00:00-08:00"));
+    // The occurrence in the DST gap is shifted forward
+    assert_eq!(
+        starts("gap"),
+        vec![at("2018-03-10T02:30:00-08:00"), at("2018-03-11T03:30:00-07:00")]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Unknown timezone Nowhere")]
+fn panic_unknown_timezone() {
+    parse(
+        "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+UID:unknown\r
+DTSTART;TZID=Nowhere:20180208T090000\r
+DURATION:PT30M\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+    );
+}
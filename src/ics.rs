@@ -0,0 +1,216 @@
+// Calendars exported as iCalendar files, one per person, to run without any
+// calendar provider. Nothing can be booked in them.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+use bio::data_structures::interval_tree::IntervalTree;
+use chrono;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use backend::{CalendarBackend, NewEvent};
+use fixtures;
+use ical;
+use types::{CalendarSettings, IcsConfig, MeetingsTree, Room};
+
+pub struct IcsFiles {
+    pub config: IcsConfig,
+}
+
+// The timezone of floating times, from the X-WR-TIMEZONE most exports have
+fn calendar_timezone(calendar: &ical::Component) -> Option<Tz> {
+    calendar
+        .find_all("VCALENDAR")
+        .iter()
+        .filter_map(|k| k.property("X-WR-TIMEZONE"))
+        .filter_map(|k| k.value.parse().ok())
+        .next()
+}
+
+fn events_to_tree(
+    calendar: &ical::Component,
+    person: &str,
+    window: &Range<DateTime<chrono::Utc>>,
+    ignore_all_day_events: bool,
+    ignore_meetings_with_no_response: bool,
+) -> MeetingsTree {
+    let tz = calendar_timezone(calendar).unwrap_or(Tz::UTC);
+    let mut tree = IntervalTree::new();
+    for (event, start, end) in ical::expand(&calendar.find_all("VEVENT"), &tz, &window.end) {
+        let blocks = ical::blocks(
+            event,
+            person,
+            ignore_all_day_events,
+            ignore_meetings_with_no_response,
+        );
+        if blocks && end > window.start {
+            let id = event.property("UID").map(|k| k.value.to_string()).unwrap_or_default();
+            tree.insert(start..end, id);
+        }
+    }
+    tree
+}
+
+impl IcsFiles {
+    // The file listed for the person, or the one named after them in the
+    // directory. Only the people listed without a file have none, a missing
+    // file would take them as free without anyone noticing.
+    fn path(&self, person: &str) -> Option<String> {
+        match (self.config.files.get(person), self.config.directory.as_ref()) {
+            (Some(k), _) => k.clone(),
+            (None, Some(k)) => {
+                let path = Path::new(k).join(format!("{}.ics", person));
+                if !path.exists() {
+                    panic!("No calendar file for {} in {}", person, k);
+                }
+                Some(path.to_string_lossy().to_string())
+            }
+            (None, None) => panic!("No calendar file for {}", person),
+        }
+    }
+
+    // None for the people listed without a calendar file, like guests
+    fn read(&self, person: &str) -> Option<ical::Component> {
+        let path = self.path(person)?;
+        let mut file =
+            File::open(&path).unwrap_or_else(|_| panic!("Cannot open the calendar {}", path));
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .expect("something went wrong reading the file");
+        Some(ical::parse(&contents))
+    }
+}
+
+impl CalendarBackend for IcsFiles {
+    fn fetch_availability(
+        &self,
+        people: Vec<String>,
+        window: &Range<DateTime<chrono::Utc>>,
+        ignore_all_day_events: bool,
+        ignore_meetings_with_no_response: bool,
+    ) -> HashMap<String, MeetingsTree> {
+        people
+            .into_iter()
+            .map(|k| {
+                let tree = match self.read(&k) {
+                    Some(calendar) => events_to_tree(
+                        &calendar,
+                        &k,
+                        window,
+                        ignore_all_day_events,
+                        ignore_meetings_with_no_response,
+                    ),
+                    None => IntervalTree::new(),
+                };
+                (k, tree)
+            })
+            .collect()
+    }
+
+    // Calendar files do not have working hours
+    fn fetch_settings(
+        &self,
+        people: Vec<String>,
+        _: &Range<DateTime<chrono::Utc>>,
+    ) -> HashMap<String, CalendarSettings> {
+        people
+            .into_iter()
+            .filter_map(|k| {
+                let timezone = calendar_timezone(&self.read(&k)?)?;
+                Some((
+                    k,
+                    CalendarSettings {
+                        timezone,
+                        working_hours: None,
+                        working_days: Vec::new(),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn create_events(&self, _: &[NewEvent]) -> Vec<String> {
+        panic!("Cannot book meetings in calendar files");
+    }
+
+    fn update_event(&self, _: &str, _: &NewEvent) {
+        panic!("Cannot book meetings in calendar files");
+    }
+
+    fn delete_event(&self, _: &str) {
+        panic!("Cannot book meetings in calendar files");
+    }
+
+    fn list_rooms(&self) -> Vec<Room> {
+        Vec::new()
+    }
+
+    fn can_book(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn test_ics_files() {
+    let directory = ::std::env::temp_dir().join("optirust_test_ics_files");
+    ::std::fs::create_dir_all(&directory).unwrap();
+    let laurent = directory.join("laurent.charignon@foo.com.ics");
+    File::create(&laurent)
+        .unwrap()
+        .write_all(fixtures::ics_calendar().as_bytes())
+        .unwrap();
+    let mut files = HashMap::new();
+    files.insert(
+        "contact@laurent.com".to_string(),
+        Some(laurent.to_string_lossy().to_string()),
+    );
+    files.insert("guest@other.com".to_string(), None);
+    let backend = IcsFiles {
+        config: IcsConfig {
+            directory: Some(directory.to_string_lossy().to_string()),
+            files,
+        },
+    };
+
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let window = at("2018-02-08T00:00:00Z")..at("2018-02-24T00:00:00Z");
+    let people = vec![
+        "laurent.charignon@foo.com".to_string(),
+        "contact@laurent.com".to_string(),
+        "guest@other.com".to_string(),
+    ];
+    let avail = backend.fetch_availability(people.clone(), &window, true, true);
+    let mut busy = avail["laurent.charignon@foo.com"]
+        .find(window.clone())
+        .map(|k| k.interval().start..k.interval().end)
+        .collect::<Vec<Range<DateTime<chrono::Utc>>>>();
+    busy.sort_by_key(|k| k.start);
+    // Every weekday at 10am Paris time but the excluded monday, the declined
+    // event and the all day event are free
+    assert_eq!(busy.len(), 11);
+    assert_eq!(busy[0], at("2018-02-08T09:00:00Z")..at("2018-02-08T09:30:00Z"));
+    assert!(!busy.contains(&(at("2018-02-12T09:00:00Z")..at("2018-02-12T09:30:00Z"))));
+    // Read from the file listed for contact, who did not decline
+    assert_eq!(avail["contact@laurent.com"].find(window.clone()).count(), 12);
+    // Free when listed without a file
+    assert_eq!(avail["guest@other.com"].find(window.clone()).count(), 0);
+
+    let settings = backend.fetch_settings(people, &window);
+    assert_eq!(settings["contact@laurent.com"].timezone, ::chrono_tz::Europe::Paris);
+    assert!(!settings.contains_key("guest@other.com"));
+}
+
+#[test]
+#[should_panic(expected = "No calendar file for missing@foo.com")]
+fn panic_missing_calendar_file() {
+    let directory = ::std::env::temp_dir().join("optirust_test_missing_ics");
+    let backend = IcsFiles {
+        config: IcsConfig {
+            directory: Some(directory.to_string_lossy().to_string()),
+            files: HashMap::new(),
+        },
+    };
+    let now = chrono::Utc::now();
+    backend.fetch_availability(vec!["missing@foo.com".to_string()], &(now..now), true, true);
+}
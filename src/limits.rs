@@ -0,0 +1,278 @@
+// Per person daily limits turned into constraints of the problem: a cap on
+// the time spent in meetings and a free block to keep for focus time. The
+// meetings already in the calendars count as well as the candidates.
+use std::cmp;
+use std::collections::HashMap;
+use std::ops::Range;
+use chrono;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use fixtures;
+use solver::{Constraint, Relation};
+use types;
+use types::{DailyLimit, DesiredMeeting, MeetingCandidate, MeetingsTree};
+
+// Granularity of the free blocks considered for focus time
+const FOCUS_STEP_MINUTES: i64 = 15;
+
+type UtcRange = Range<DateTime<chrono::Utc>>;
+// (person, day) -> (timezone, [(candidate id, time held)])
+type HeldPerDay = HashMap<(String, NaiveDate), (Tz, Vec<(String, UtcRange)>)>;
+
+fn overlap_minutes(a: &UtcRange, b: &UtcRange) -> i64 {
+    let start = cmp::max(a.start, b.start);
+    let end = cmp::min(a.end, b.end);
+    if end > start {
+        end.signed_duration_since(start).num_minutes()
+    } else {
+        0
+    }
+}
+
+fn local_time(tz: &Tz, date: NaiveDate, hours: u32, minutes: u32) -> DateTime<chrono::Utc> {
+    types::from_local_time(tz, &date.and_hms(hours, minutes, 0))
+}
+
+// Everyone invited to the meetings to schedule, in any role
+pub fn everyone(desired_meetings: &[DesiredMeeting]) -> Vec<String> {
+    let mut res = Vec::new();
+    for m in desired_meetings {
+        res.extend(m.attendees.iter().cloned());
+        res.extend(m.sessions.iter().flat_map(|k| k.interviewers.iter().cloned()));
+        res.extend(m.pools.iter().flat_map(|k| k.members.iter().cloned()));
+    }
+    res.sort();
+    res.dedup();
+    res
+}
+
+// Candidates holding each person, per day in the timezone of the meeting
+fn candidates_per_person_and_day(
+    desired_meetings: &[DesiredMeeting],
+    candidates: &HashMap<String, MeetingCandidate>,
+) -> HeldPerDay {
+    let timezones: HashMap<&str, Tz> = desired_meetings
+        .iter()
+        .map(|m| (m.title.as_str(), m.timezone))
+        .collect();
+    let mut res = HashMap::new();
+    for (id, c) in candidates {
+        let tz = timezones[c.title.as_str()];
+        for (who, range) in c.reservations() {
+            if c.room.as_ref() == Some(&who) {
+                continue;
+            }
+            let day = range.start.with_timezone(&tz).date().naive_local();
+            res.entry((who, day))
+                .or_insert_with(|| (tz, Vec::new()))
+                .1
+                .push((id.to_string(), range));
+        }
+    }
+    res
+}
+
+// sum(minutes of the candidate that day * candidate) <= what is left of the
+// cap once the existing meetings are counted
+fn meeting_cap_constraint(
+    max_minutes: i64,
+    day: &UtcRange,
+    busy: &MeetingsTree,
+    held: &[(String, UtcRange)],
+) -> Option<Constraint> {
+    let existing = busy.find(day.clone())
+        .map(|k| overlap_minutes(k.interval(), day))
+        .sum::<i64>();
+    let mut minutes: HashMap<&str, i64> = HashMap::new();
+    for (id, range) in held {
+        *minutes.entry(id.as_str()).or_insert(0) += overlap_minutes(range, day);
+    }
+    let left = cmp::max(0, max_minutes - existing);
+    // Nothing to enforce if all the candidates together fit
+    if minutes.values().sum::<i64>() <= left {
+        return None;
+    }
+    let mut terms = minutes
+        .into_iter()
+        .filter(|k| k.1 > 0)
+        .map(|k| (k.1, k.0.to_string()))
+        .collect::<Vec<(i64, String)>>();
+    terms.sort_by(|a, b| a.1.cmp(&b.1));
+    Some(Constraint {
+        terms,
+        relation: Relation::Le,
+        rhs: left,
+    })
+}
+
+// One variable per block of the working hours free of existing meetings,
+// set if the block is kept free. A kept block excludes every candidate
+// overlapping it:
+// sum(overlapping candidates) + count * block <= count
+// and at least one block is kept: -sum(blocks) <= -1
+// Nothing is enforced if no block is free already, or if one of them cannot
+// be taken by any candidate.
+fn focus_constraints(
+    name: &str,
+    min_minutes: i64,
+    working_hours: &UtcRange,
+    busy: &MeetingsTree,
+    held: &[(String, UtcRange)],
+) -> Vec<Constraint> {
+    let length = chrono::Duration::minutes(min_minutes);
+    let step = chrono::Duration::minutes(FOCUS_STEP_MINUTES);
+    let mut blocks = Vec::new();
+    let mut t = working_hours.start;
+    while t + length <= working_hours.end {
+        if busy.find(t..t + length).count() == 0 {
+            blocks.push(t..t + length);
+        }
+        t = t + step;
+    }
+
+    let mut res = Vec::new();
+    let mut cover = Vec::new();
+    for (k, block) in blocks.iter().enumerate() {
+        let mut terms = held.iter()
+            .filter(|h| overlap_minutes(&h.1, block) > 0)
+            .map(|h| (1, h.0.to_string()))
+            .collect::<Vec<(i64, String)>>();
+        terms.sort();
+        terms.dedup();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let var = format!("{}_{}", name, k);
+        let count = terms.len() as i64;
+        terms.push((count, var.to_string()));
+        res.push(Constraint {
+            terms,
+            relation: Relation::Le,
+            rhs: count,
+        });
+        cover.push((-1, var));
+    }
+    if cover.is_empty() {
+        return Vec::new();
+    }
+    res.push(Constraint {
+        terms: cover,
+        relation: Relation::Le,
+        rhs: -1,
+    });
+    res
+}
+
+pub fn daily_limit_constraints(
+    limits: &[DailyLimit],
+    desired_meetings: &[DesiredMeeting],
+    candidates: &HashMap<String, MeetingCandidate>,
+    avail: &HashMap<String, MeetingsTree>,
+) -> Vec<Constraint> {
+    let per_day = candidates_per_person_and_day(desired_meetings, candidates);
+    let mut keys = per_day.keys().collect::<Vec<&(String, NaiveDate)>>();
+    keys.sort();
+
+    let mut res = Vec::new();
+    for (n, limit) in limits.iter().enumerate() {
+        let people = limit
+            .attendees
+            .clone()
+            .unwrap_or_else(|| everyone(desired_meetings));
+        for (k, key) in keys.iter().enumerate() {
+            let &(ref who, date) = *key;
+            if !people.contains(who) {
+                continue;
+            }
+            let (tz, ref held) = per_day[*key];
+            let busy = &avail[who];
+            if let Some(max_minutes) = limit.max_meeting_minutes {
+                let day = local_time(&tz, date, 0, 0)..local_time(&tz, date.succ(), 0, 0);
+                res.extend(meeting_cap_constraint(max_minutes, &day, busy, held));
+            }
+            if let Some(min_minutes) = limit.min_focus_minutes {
+                let working_hours = match limit.working_hours {
+                    Some(ref h) => {
+                        local_time(&tz, date, h.from.hours, h.from.minutes)
+                            ..local_time(&tz, date, h.to.hours, h.to.minutes)
+                    }
+                    None => local_time(&tz, date, 9, 0)..local_time(&tz, date, 18, 0),
+                };
+                res.extend(focus_constraints(
+                    &format!("focus_{}_{}", n, k),
+                    min_minutes,
+                    &working_hours,
+                    busy,
+                    held,
+                ));
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+fn solve_with_limit(limit: DailyLimit, until: &str) -> DateTime<Tz> {
+    use solver::{solve_with_builtin_solver, SolverInput};
+    use types::{read_input_str, Options};
+    let options = Options {
+        backend: Box::new(fixtures::FakeBackend::new(fixtures::fetch_busy_morning)),
+        daily_limits: vec![limit],
+        ..fixtures::test_options()
+    };
+    let mut desired_meetings = read_input_str(&fixtures::test_limits_input());
+    desired_meetings[0].max_date = until.parse().unwrap();
+    let input = SolverInput::new_from_desired_meetings_and_opts(desired_meetings, &options);
+    let solution = solve_with_builtin_solver(&input).expect("Problem should be feasible");
+    solution.values().next().unwrap().start.with_timezone(&::chrono_tz::US::Pacific)
+}
+
+#[test]
+fn test_daily_meeting_cap() {
+    // Without a limit the meeting is booked right after the busy morning, but
+    // three hours of meetings already fill the Thursday
+    let limit = DailyLimit {
+        attendees: Some(vec!["laurent.charignon@foo.com".to_string()]),
+        max_meeting_minutes: Some(180),
+        min_focus_minutes: None,
+        working_hours: None,
+    };
+    let start = solve_with_limit(limit, "2018-02-09T17:00:00-08:00");
+    assert_eq!(start.day(), 9);
+}
+
+#[test]
+fn test_focus_block_is_kept() {
+    use types::{HourRange, HourSpec};
+    // Busy all morning, a 4 hour block before 17 has to start between 12 and
+    // 13, the only way to keep one is to book the meeting from 16 to 17
+    let limit = DailyLimit {
+        attendees: None,
+        max_meeting_minutes: None,
+        min_focus_minutes: Some(240),
+        working_hours: Some(HourRange {
+            from: HourSpec {
+                hours: 9,
+                minutes: 0,
+                seconds: 0,
+            },
+            to: HourSpec {
+                hours: 17,
+                minutes: 0,
+                seconds: 0,
+            },
+        }),
+    };
+    let start = solve_with_limit(limit, "2018-02-08T17:00:00-08:00");
+    assert_eq!((start.day(), start.hour()), (8, 16));
+}
+
+#[test]
+fn test_days_starting_in_a_dst_gap() {
+    // Clocks go from 0:00 to 1:00 in Havana, the day starts at 1:00
+    let tz: Tz = "America/Havana".parse().unwrap();
+    let date = NaiveDate::from_ymd(2018, 3, 11);
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    assert_eq!(local_time(&tz, date, 0, 0), at("2018-03-11T01:00:00-04:00"));
+    assert_eq!(local_time(&tz, date.succ(), 0, 0), at("2018-03-12T00:00:00-04:00"));
+}
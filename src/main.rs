@@ -12,16 +12,29 @@ extern crate hyper_rustls;
 extern crate rayon;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 extern crate serde_yaml;
 extern crate yup_oauth2 as oauth2;
 
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
 use std::process;
 
 mod app;
+mod backend;
+mod bnb;
+mod caldav;
+mod diagnosis;
+mod fairness;
 mod fixtures;
 mod gcal;
 mod gen;
+mod graph;
+mod ical;
+mod ics;
+mod limits;
 mod solver;
 mod types;
 
@@ -29,20 +42,41 @@ use types::{Config, Solution};
 
 fn main() {
     let matches = app::build_app().get_matches();
-    let options = {
-        let config_filename = matches.value_of("config");
-        if let Some(config_filename) = config_filename {
-            let config = Config::from_file(config_filename);
-            let (c, d, e) = (config.clone(), config.clone(), config.clone());
-            let room_picker = Box::new(move |k| c.room_picker(k));
+    let config = matches.value_of("config").map(Config::from_file);
+    let backend = backend::backend_from_config(
+        config.as_ref(),
+        matches.value_of("fetch") == Some("freebusy"),
+    );
+    if matches.is_present("book") && !backend.can_book() {
+        eprintln!("The meetings cannot be booked with this backend, run without --book");
+        process::exit(1);
+    }
+    let mut options = {
+        if let Some(mut config) = config {
+            if config.discover_rooms {
+                let mut rooms = config.rooms.unwrap_or_default();
+                rooms.extend(backend.list_rooms());
+                config.rooms = Some(rooms);
+            }
+            let (c, d, e, f) = (config.clone(), config.clone(), config.clone(), config.clone());
+            let room_picker = Box::new(move |k, l: &[String]| c.room_picker(k, l));
             let reject_date = Box::new(move |k| d.reject_date_fn(k));
             let reject_datetime = Box::new(move |k, l| e.reject_datetime_fn(k, l));
+            let buffer = Box::new(move |k: &str| f.buffer(k));
+            let scoring = config.scoring.clone().unwrap_or_default();
+            let score = Box::new(move |m: &_, s: &_, e: &_, a: &[String], av: &_| {
+                scoring.score(m, s, e, a, av)
+            });
             types::Options {
                 room_picker_fn: room_picker,
                 reject_date_fn: reject_date,
                 reject_datetime_fn: reject_datetime,
                 ignore_all_day_events: config.ignore_all_day_events,
                 ignore_meetings_with_no_response: config.ignore_meetings_with_no_response,
+                daily_limits: config.daily_limits.clone().unwrap_or_default(),
+                buffer_fn: buffer,
+                scoring_fn: score,
+                calendar_working_hours: config.calendar_working_hours.clone(),
                 ..Default::default()
             }
         } else {
@@ -52,36 +86,66 @@ fn main() {
         }
     };
 
+    options.solver_fn = solver::solver_from_name(matches.value_of("solver"));
+    options.backend = backend;
+    options.best_effort = matches.is_present("best-effort");
+    if let Some(preferences) = matches.value_of("preferences") {
+        options.preferences = types::read_preferences(preferences);
+    }
+
     let input = types::read_input(
         matches
             .value_of("input")
             .expect("Please give a valid input file"),
     );
+    options.add_calendar_settings(&input);
 
     let solver_input = solver::SolverInput::new_from_desired_meetings_and_opts(input, &options);
 
+    let without_candidates = solver_input.meetings_without_candidates();
+    for m in &without_candidates {
+        eprintln!(
+            "WARNING: no possible slot for {:?}: {}",
+            m.title, solver_input.stats[&m.title]
+        );
+    }
+    if !without_candidates.is_empty() && !options.best_effort {
+        eprintln!("Some meetings cannot be scheduled, use --best-effort to book the others");
+        process::exit(1);
+    }
+
     let sol = match (options.solver_fn)(&solver_input) {
         Some(m) => Solution {
             solved: true,
+            unscheduled: solver_input.explain_unscheduled(&m),
             candidates: m,
         },
         None => Solution {
             solved: false,
             candidates: HashMap::new(),
+            unscheduled: HashMap::new(),
         },
     };
 
     if !sol.solved {
         eprintln!("Cannot find meetings to solve the constraints!");
+        eprint!("{}", diagnosis::Diagnosis::new(&solver_input));
         process::exit(1);
     }
 
     println!("{:?}", sol);
+    if let Some(path) = matches.value_of("export") {
+        let invites = backend::export(&sol, matches.value_of("organizer"), true);
+        File::create(path)
+            .and_then(|mut k| k.write_all(invites.as_bytes()))
+            .expect("Cannot write the invites");
+        println!("Invites written to {}", path);
+    }
     match matches.occurrences_of("book") {
         0 => println!("Dry run mode, not booking!"),
         _ => {
             println!("Booking!");
-            gcal::book_with_api(&sol, true);
+            backend::book(&*options.backend, &sol, true);
         }
     }
 }
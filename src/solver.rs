@@ -1,26 +1,52 @@
 use std::collections::HashMap;
+use std::env;
 use std::fs::File;
 use std::collections::HashSet;
 use std::io::prelude::*;
 use std::iter::FromIterator;
+use std::ops::Range;
 use std::process::Command;
+use std::slice;
 use chrono;
 use chrono::prelude::*;
+use bnb;
+use fairness;
 use fixtures;
 use gen;
+use limits;
 use bio::data_structures::interval_tree::IntervalTree;
-use types::{DesiredMeeting, Meeting, MeetingCandidate, MeetingsTree, Options, RoomPickerFnType,
-            ScoringFnType};
+use chrono_tz::Tz;
+use types::{CandidateStats, DesiredMeeting, Meeting, MeetingCandidate, MeetingsTree, Options, RoomPickerFnType,
+            Session, SessionSlot, SolverFnType};
 
-#[derive(Debug)]
+// Score added to a candidate for every optional attendee available
+const OPTIONAL_ATTENDEE_SCORE: usize = 300;
+// Orders of the sessions of a loop considered per start time, each one is a
+// candidate for every choice of pool members and every room
+const MAX_SESSION_ORDERS: usize = 6;
+
+#[derive(Debug, Clone)]
 pub struct SolverInput {
     pub intersections: HashSet<Vec<String>>,
     pub candidate_per_desired_meeting: HashMap<String, Vec<String>>,
     pub candidates: HashMap<String, MeetingCandidate>,
     pub desired_meetings: Vec<DesiredMeeting>,
+    // Book as many meetings as possible instead of requiring all of them
+    pub best_effort: bool,
+    // Why slots of each desired meeting were not turned into candidates
+    pub stats: HashMap<String, CandidateStats>,
+    // Constraints enforcing the daily limits of the attendees and fairness
+    // between time zones, they can use variables of their own on top of the
+    // candidates
+    pub limits: Vec<Constraint>,
+    // Cost of these variables, added to the objective
+    pub penalties: Vec<(i64, String)>,
 }
 
 pub fn solve_with_cbc_solver(s: &SolverInput) -> Option<HashMap<DesiredMeeting, MeetingCandidate>> {
+    if !s.is_satisfiable() {
+        return None;
+    }
     let mut buffer = File::create("temp.lp")
         .expect("Cannot create temporary file to store the optimization problem");
     buffer
@@ -41,96 +67,578 @@ pub fn solve_with_cbc_solver(s: &SolverInput) -> Option<HashMap<DesiredMeeting,
     read_cbc_solver_solution(&contents, s)
 }
 
+// Solve the problem in process, without depending on an external binary
+pub fn solve_with_builtin_solver(
+    s: &SolverInput,
+) -> Option<HashMap<DesiredMeeting, MeetingCandidate>> {
+    if !s.is_satisfiable() {
+        return None;
+    }
+    let (score, chosen) = match bnb::solve(&s.to_problem()) {
+        bnb::Outcome::Optimal(score, chosen) => (score, chosen),
+        bnb::Outcome::LimitReached(Some(best)) => {
+            eprintln!("Builtin solver node limit reached, the solution may not be optimal");
+            best
+        }
+        bnb::Outcome::LimitReached(None) => {
+            eprintln!("Builtin solver node limit reached before finding any solution");
+            return None;
+        }
+        bnb::Outcome::Infeasible => return None,
+    };
+    println!("Total score is {}", score);
+    Some(chosen_candidates_to_meetings(&chosen, s))
+}
+
+// Is there a cbc binary in the PATH?
+fn cbc_in_path() -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|p| p.join("cbc").is_file()))
+        .unwrap_or(false)
+}
+
+// Return the solver with the given name. Without a name, cbc is used if it is
+// installed and the builtin solver otherwise.
+pub fn solver_from_name(name: Option<&str>) -> SolverFnType {
+    match name {
+        Some("cbc") => Box::new(solve_with_cbc_solver),
+        Some("builtin") => Box::new(solve_with_builtin_solver),
+        Some(k) => panic!("Unknown solver {:?}", k),
+        None => if cbc_in_path() {
+            Box::new(solve_with_cbc_solver)
+        } else {
+            Box::new(solve_with_builtin_solver)
+        },
+    }
+}
+
 // Extract the list of attendees emails from the input and config
 fn extract_attendees(i: &[DesiredMeeting], c: &RoomPickerFnType) -> Vec<String> {
     let mut s: HashSet<String> = HashSet::new();
     for m in i {
         let attendees = m.attendees
             .iter()
+            .chain(m.optional_attendees.iter())
+            .chain(m.sessions.iter().flat_map(|k| k.interviewers.iter()))
+            .chain(m.pools.iter().flat_map(|k| k.members.iter()))
             .map(|k| k.to_string())
             .collect::<Vec<String>>();
-        if let Some(rooms) = c(attendees.len()) {
+        if let Some(rooms) = c(m.room_size(), &m.room_tags) {
             s.extend(rooms);
         }
         s.extend(attendees);
     }
     Vec::from_iter(s.into_iter())
 }
-// Generated a candidate for a desired meeting for the interval specified by Meeting
-// None if not possible (no availability)
-fn generate_meeting_candidate(
+
+// When the calendars are needed: when the meetings can happen, with a day of
+// margin on both sides for the buffers, the scoring and the daily limits
+pub fn fetch_window(desired_meetings: &[DesiredMeeting]) -> Range<DateTime<chrono::Utc>> {
+    let margin = chrono::Duration::days(1);
+    let windows = desired_meetings
+        .iter()
+        .map(|m| m.window())
+        .collect::<Vec<Range<DateTime<chrono::Utc>>>>();
+    let start = windows.iter().map(|k| k.start).min().unwrap_or_else(chrono::Utc::now);
+    let end = windows.iter().map(|k| k.end).max().unwrap_or(start);
+    start - margin..end + margin
+}
+
+// All the ways to pick k elements of l, keeping their order
+fn combinations(l: &[String], k: usize) -> Vec<Vec<String>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    if l.len() < k {
+        return Vec::new();
+    }
+    let mut res = combinations(&l[1..], k - 1)
+        .into_iter()
+        .map(|mut c| {
+            c.insert(0, l[0].to_string());
+            c
+        })
+        .collect::<Vec<Vec<String>>>();
+    res.extend(combinations(&l[1..], k));
+    res
+}
+
+// Is who free from start to end, keeping their buffer before and after? The
+// hours they never want meetings in count as busy.
+fn is_free_during(
+    avail: &HashMap<String, MeetingsTree>,
+    opts: &Options,
+    tz: &Tz,
+    who: &str,
+    start: DateTime<chrono::Utc>,
+    end: DateTime<chrono::Utc>,
+) -> bool {
+    if let Some(p) = opts.preferences.get(who) {
+        if !p.accepts(tz, start, end) {
+            return false;
+        }
+    }
+    let buffer = (opts.buffer_fn)(who);
+    avail[who].find(start - buffer..end + buffer).count() == 0
+}
+
+// Orders in which the sessions of a loop starting at start can take place,
+// with every interviewer free during their session. Only the first
+// MAX_SESSION_ORDERS found are kept, the closest to the order of the input,
+// the second value tells whether other orders were left out.
+fn session_orders(
     tm: &DesiredMeeting,
+    start: DateTime<chrono::Utc>,
     avail: &HashMap<String, MeetingsTree>,
-    ident: String,
-    room_picker: &RoomPickerFnType,
-    scoring_fn: &ScoringFnType,
+    opts: &Options,
+) -> (Vec<Vec<usize>>, bool) {
+    let mut res = Vec::new();
+    extend_session_order(tm, start, avail, opts, &mut Vec::new(), &mut res);
+    let capped = res.len() > MAX_SESSION_ORDERS;
+    res.truncate(MAX_SESSION_ORDERS);
+    (res, capped)
+}
+
+fn extend_session_order(
+    tm: &DesiredMeeting,
+    start: DateTime<chrono::Utc>,
+    avail: &HashMap<String, MeetingsTree>,
+    opts: &Options,
+    order: &mut Vec<usize>,
+    res: &mut Vec<Vec<usize>>,
+) {
+    if order.len() == tm.sessions.len() {
+        res.push(order.clone());
+        return;
+    }
+    for (k, s) in tm.sessions.iter().enumerate() {
+        // One more than kept to know if some are left out
+        if order.contains(&k) || res.len() > MAX_SESSION_ORDERS {
+            continue;
+        }
+        let end = start + s.duration;
+        if s.interviewers
+            .iter()
+            .all(|p| is_free_during(avail, opts, &tm.timezone, p, start, end))
+        {
+            order.push(k);
+            extend_session_order(tm, end, avail, opts, order, res);
+            order.pop();
+        }
+    }
+}
+
+// Sub-slots of the sessions of a loop starting at start, in the given order
+fn place_sessions(sessions: &[Session], order: &[usize], start: DateTime<chrono::Utc>) -> Vec<SessionSlot> {
+    let mut t = start;
+    order
+        .iter()
+        .map(|k| {
+            let s = &sessions[*k];
+            let slot = SessionSlot {
+                title: s.title.to_string(),
+                interviewers: s.interviewers.clone(),
+                start: t,
+                end: t + s.duration,
+            };
+            t = slot.end;
+            slot
+        })
+        .collect()
+}
+// Generate the candidates for a desired meeting for the interval specified by
+// Meeting, one per room that is free at that time so that the solver decides
+// which room to book. Rooms earlier in the list given by the room picker get a
+// slightly better score. There is also one candidate per choice of free pool
+// members and, for an interview loop, per possible order of its sessions.
+// Empty if not possible (no availability), in which case
+// the busy attendees are recorded in stats. Attendees and rooms must be free
+// for their buffer around the slot as well.
+fn generate_meeting_candidates(
+    tm: &DesiredMeeting,
+    avail: &HashMap<String, MeetingsTree>,
+    ident: &str,
+    opts: &Options,
     i: &Meeting,
-) -> Option<MeetingCandidate> {
-    let possible_rooms: Option<Vec<String>> = room_picker(tm.attendees.len());
+    stats: &mut CandidateStats,
+) -> Vec<MeetingCandidate> {
+    let room_picker = &opts.room_picker_fn;
+    let scoring_fn = &opts.scoring_fn;
+    let tz = &tm.timezone;
+    let possible_rooms: Vec<String> = match room_picker(tm.room_size(), &tm.room_tags) {
+        Some(ref k) if k.is_empty() => {
+            stats.rejected_by_room += 1;
+            return Vec::new();
+        }
+        k => k.unwrap_or_default(),
+    };
     let mandatory_attendees = &tm.attendees;
-    let conflicts: usize = mandatory_attendees
+    // A recurring meeting needs the same slot to work for (enough of) its
+    // occurrences, a regular one has a single occurrence
+    let occurrences = tm.occurrences(i.start, i.end);
+    let min_free_occurrences = tm.recurrence
+        .as_ref()
+        .map_or(1, |r| r.min_free_occurrences);
+    let is_free = |who: &str| {
+        occurrences
+            .iter()
+            .all(|o| is_free_during(avail, opts, tz, who, o.0, o.1))
+    };
+    let free_occurrences = occurrences
         .iter()
-        .map(|k| avail[k].find(i.start..i.end).count())
-        .sum();
+        .filter(|o| {
+            mandatory_attendees
+                .iter()
+                .all(|k| is_free_during(avail, opts, tz, k, o.0, o.1))
+        })
+        .count();
 
-    if conflicts != 0 {
-        return None;
+    // Outside the working hours of an attendee or in their no meeting hours
+    let refused = occurrences.iter().any(|o| {
+        mandatory_attendees.iter().any(|k| {
+            opts.preferences
+                .get(k)
+                .is_some_and(|p| !p.accepts(tz, o.0, o.1))
+        })
+    });
+    if refused {
+        stats.rejected_by_preferences += 1;
+        return Vec::new();
     }
 
-    // What is a suitable room?
-    let mut suitable_room: Option<String> = None;
-    if let Some(possible_rooms) = possible_rooms {
-        for r in &possible_rooms {
-            if avail[r].find(i.start..i.end).count() == 0 {
-                suitable_room = Some(r.to_string());
-                break;
-            }
+    if free_occurrences < min_free_occurrences {
+        stats.rejected_by_conflict += 1;
+        for k in mandatory_attendees.iter().filter(|k| !is_free(k)) {
+            *stats.conflicts_per_attendee.entry(k.to_string()).or_insert(0) += 1;
         }
+        return Vec::new();
     }
 
-    // Create a suitable candidate
-    Some(MeetingCandidate {
+    // Optional attendees never prevent a slot, but the more of them can come
+    // the better
+    let optional_attendees_available = tm.optional_attendees
+        .iter()
+        .filter(|k| is_free(k))
+        .count();
+
+    // The score of the slot adjusted by the preferences of the attendees
+    let score_for = |start: &DateTime<chrono::Utc>, end: &DateTime<chrono::Utc>, who: &[String]| {
+        let mut score = scoring_fn(tm, start, end, who, avail);
+        for p in who.iter().filter_map(|k| opts.preferences.get(k)) {
+            score = (score + p.bonus(tz, *start)).saturating_sub(p.penalty(tz, *start));
+        }
+        score
+    };
+
+    let score = occurrences
+        .iter()
+        .map(|o| score_for(&o.0, &o.1, mandatory_attendees))
+        .sum::<usize>() / occurrences.len();
+
+    let candidate = MeetingCandidate {
         title: tm.title.to_string(),
-        id: ident,
+        id: ident.to_string(),
         start: i.start,
         end: i.end,
-        room: suitable_room,
-        score: scoring_fn(&i.start, &i.end, mandatory_attendees, avail),
-    })
+        room: None,
+        attendees: mandatory_attendees.clone(),
+        score: score + OPTIONAL_ATTENDEE_SCORE * optional_attendees_available,
+        recurrences: occurrences[1..].to_vec(),
+        sessions: Vec::new(),
+    };
+
+    // Every way to pick the pool members among the ones free for the slot.
+    // Nobody is picked twice, nor picked when already invited.
+    let mut choices: Vec<Vec<String>> = vec![Vec::new()];
+    for p in &tm.pools {
+        let free = p.members
+            .iter()
+            .filter(|k| is_free(k) && !mandatory_attendees.contains(k))
+            .cloned()
+            .collect::<Vec<String>>();
+        if free.len() < p.pick {
+            stats.rejected_by_conflict += 1;
+            for k in p.members.iter().filter(|k| !is_free(k)) {
+                *stats.conflicts_per_attendee.entry(k.to_string()).or_insert(0) += 1;
+            }
+            return Vec::new();
+        }
+        let picks = combinations(&free, p.pick);
+        choices = choices
+            .iter()
+            .flat_map(|c| {
+                picks
+                    .iter()
+                    .filter(move |k| k.iter().all(|m| !c.contains(m)))
+                    .map(move |k| [c.clone(), k.clone()].concat())
+            })
+            .collect();
+    }
+    if choices.is_empty() {
+        stats.rejected_by_conflict += 1;
+        return Vec::new();
+    }
+
+    // The picked members attend like the mandatory attendees
+    let candidates = if tm.pools.is_empty() {
+        vec![candidate]
+    } else {
+        choices
+            .into_iter()
+            .enumerate()
+            .map(|(n, chosen)| {
+                let chosen_score = occurrences
+                    .iter()
+                    .map(|o| score_for(&o.0, &o.1, &chosen))
+                    .sum::<usize>() / occurrences.len();
+                let mut attendees = candidate.attendees.clone();
+                attendees.extend(chosen);
+                MeetingCandidate {
+                    id: format!("{}_c{}", ident, n),
+                    attendees,
+                    score: candidate.score + chosen_score,
+                    ..candidate.clone()
+                }
+            })
+            .collect()
+    };
+
+    let candidates = if tm.sessions.is_empty() {
+        candidates
+    } else {
+        let (orders, capped) = session_orders(tm, i.start, avail, opts);
+        if capped {
+            stats.capped_session_orders += 1;
+        }
+        if orders.is_empty() {
+            stats.rejected_by_conflict += 1;
+            for k in tm.sessions
+                .iter()
+                .flat_map(|s| s.interviewers.iter())
+                .filter(|k| !is_free(k))
+            {
+                *stats.conflicts_per_attendee.entry(k.to_string()).or_insert(0) += 1;
+            }
+            return Vec::new();
+        }
+        let mut res = Vec::new();
+        for c in candidates {
+            for (n, order) in orders.iter().enumerate() {
+                let sessions = place_sessions(&tm.sessions, order, i.start);
+                let sessions_score = sessions
+                    .iter()
+                    .map(|s| score_for(&s.start, &s.end, &s.interviewers))
+                    .sum::<usize>() / sessions.len();
+                res.push(MeetingCandidate {
+                    id: format!("{}_p{}", c.id, n),
+                    score: c.score + sessions_score,
+                    sessions,
+                    ..c.clone()
+                });
+            }
+        }
+        res
+    };
+
+    // Rooms have to be free for every occurrence
+    let free_rooms = possible_rooms
+        .iter()
+        .enumerate()
+        .filter(|&(_, r)| is_free(r))
+        .collect::<Vec<(usize, &String)>>();
+
+    // Without any free room we still book the meeting, just with no room
+    if free_rooms.is_empty() {
+        return candidates;
+    }
+
+    let mut res = Vec::new();
+    for c in candidates {
+        for &(k, r) in &free_rooms {
+            res.push(MeetingCandidate {
+                id: format!("{}_r{}", c.id, k),
+                room: Some(r.to_string()),
+                score: c.score + possible_rooms.len() - k,
+                ..c.clone()
+            });
+        }
+    }
+    res
 }
 
-fn build_intersections_pairs(
+// Two candidates are mutually exclusive if they need the same resource (an
+// attendee, an interviewer or a room) at the same time or closer than the
+// buffer of the resource. Meetings with nothing in common can happen in
+// parallel.
+pub fn build_intersections_pairs(
     candidates: &HashMap<String, MeetingCandidate>,
+    buffer_fn: &Fn(&str) -> chrono::Duration,
 ) -> HashSet<Vec<String>> {
-    let mut tree: IntervalTree<DateTime<chrono::Utc>, String> = IntervalTree::new();
+    // One interval tree of candidates per resource, widened by the buffer of
+    // the resource so that looking up the exact time range of another
+    // candidate finds the ones too close to it
+    let mut trees: HashMap<String, IntervalTree<DateTime<chrono::Utc>, String>> = HashMap::new();
     for it in candidates {
-        let c = it.1;
-        tree.insert(c.start..c.end, c.id.to_string());
+        for (r, range) in it.1.reservations() {
+            let buffer = buffer_fn(&r);
+            trees
+                .entry(r)
+                .or_insert_with(IntervalTree::new)
+                .insert(range.start - buffer..range.end + buffer, it.0.to_string());
+        }
     }
 
+    // Candidates of the same meeting are already exclusive, only one of them
+    // can be picked
     let mut intersections: HashSet<Vec<String>> = HashSet::new();
     for c in candidates {
         let ident = c.0;
-        let range = c.1.start..c.1.end;
-        for k in tree.find(range).map(|r| r.data()).filter(|k| k != &ident) {
-            let mut combined = vec![k.to_string(), ident.to_string()];
-            combined.sort();
-            intersections.insert(combined);
+        for (r, range) in c.1.reservations() {
+            for k in trees[&r]
+                .find(range)
+                .map(|r| r.data())
+                .filter(|k| k != &ident && candidates[*k].title != c.1.title)
+            {
+                let mut combined = vec![k.to_string(), ident.to_string()];
+                combined.sort();
+                intersections.insert(combined);
+            }
         }
     }
     intersections
 }
 
+#[test]
+fn test_build_intersections_pairs_only_on_shared_resources() {
+    let mut candidate_a = fixtures::sample_candidate_a();
+    let mut candidate_b = fixtures::sample_candidate_b();
+    candidate_a.end = candidate_a.start + chrono::Duration::minutes(30);
+    candidate_b.start = candidate_a.start;
+    candidate_b.end = candidate_a.end;
+
+    // Same time, different attendees and rooms: no conflict
+    let mut candidates = HashMap::new();
+    candidates.insert("id10873".to_string(), candidate_a.clone());
+    candidates.insert("id0".to_string(), candidate_b.clone());
+    assert_eq!(build_intersections_pairs(&candidates, &fixtures::no_buffer).len(), 0);
+
+    // Same room
+    candidate_b.room = candidate_a.room.clone();
+    candidates.insert("id0".to_string(), candidate_b.clone());
+    assert_eq!(build_intersections_pairs(&candidates, &fixtures::no_buffer).len(), 1);
+
+    // Shared attendee
+    candidate_b.room = Some("bar".to_string());
+    candidate_b.attendees.extend(candidate_a.attendees.clone());
+    candidates.insert("id0".to_string(), candidate_b.clone());
+    let intersections = build_intersections_pairs(&candidates, &fixtures::no_buffer);
+    assert_eq!(intersections.len(), 1);
+    assert!(intersections.contains(&vec!["id0".to_string(), "id10873".to_string()]));
+
+    // Candidates of the same meeting never need a pair
+    candidate_b.title = candidate_a.title.clone();
+    candidates.insert("id0".to_string(), candidate_b);
+    assert_eq!(build_intersections_pairs(&candidates, &fixtures::no_buffer).len(), 0);
+}
+
+#[test]
+fn test_buffers_keep_candidates_apart() {
+    let mut candidate_a = fixtures::sample_candidate_a();
+    let mut candidate_b = fixtures::sample_candidate_b();
+    candidate_a.end = candidate_a.start + chrono::Duration::minutes(30);
+    candidate_b.start = candidate_a.end + chrono::Duration::minutes(10);
+    candidate_b.end = candidate_b.start + chrono::Duration::minutes(30);
+    candidate_b.room = candidate_a.room.clone();
+    let mut candidates = HashMap::new();
+    candidates.insert("id10873".to_string(), candidate_a);
+    candidates.insert("id0".to_string(), candidate_b);
+    assert_eq!(build_intersections_pairs(&candidates, &fixtures::no_buffer).len(), 0);
+    // The room needs 15 minutes of cleanup, 10 are not enough
+    let cleanup = |who: &str| if who == "foo" {
+        chrono::Duration::minutes(15)
+    } else {
+        chrono::Duration::zero()
+    };
+    assert_eq!(build_intersections_pairs(&candidates, &cleanup).len(), 1);
+}
+
+#[test]
+fn test_buffers_apply_to_existing_meetings() {
+    let options = Options {
+        buffer_fn: Box::new(|_| chrono::Duration::minutes(15)),
+        ..fixtures::test_options()
+    };
+    let k = fixtures::solver_input(&fixtures::test_input(), &options);
+    // The slot ending at 14:00 right before the existing meeting is rejected
+    // too, the one starting at 15:00 leaves the 15 minutes needed
+    assert_eq!(k.candidate_per_desired_meeting["title"].len(), 109);
+    assert_eq!(k.stats["title"].rejected_by_conflict, 3);
+}
+
+#[test]
+fn test_preferences_reject_and_score_slots() {
+    let options = Options {
+        preferences: ::types::read_preferences_str(&fixtures::test_preferences()),
+        ..fixtures::test_options()
+    };
+    let (input, solution) = fixtures::solve_input(&fixtures::test_input(), options);
+    // Laurent refuses the 4 slots after 16:00 of each of the 8 days
+    assert_eq!(input.candidate_per_desired_meeting["title"].len(), 110 - 4 * 8);
+
+    // Before lunch on Thursday rather than on Friday
+    let start = solution
+        .values()
+        .find(|c| c.title == "title2")
+        .map(|c| c.start.with_timezone(&::chrono_tz::US::Pacific))
+        .unwrap();
+    assert_eq!((start.day(), start.hour()), (8, 11));
+}
+
+#[test]
+fn test_working_hours_from_calendar() {
+    use types::{HourRange, HourSpec};
+    let hours = |from: u32, to: u32| HourRange {
+        from: HourSpec {
+            hours: from,
+            minutes: 0,
+            seconds: 0,
+        },
+        to: HourSpec {
+            hours: to,
+            minutes: 0,
+            seconds: 0,
+        },
+    };
+    let mut options = Options {
+        backend: Box::new(fixtures::FakeBackend {
+            settings: fixtures::fetch_calendar_settings,
+            ..fixtures::FakeBackend::new(fixtures::fetch_results)
+        }),
+        calendar_working_hours: Some(hours(9, 18)),
+        ..fixtures::test_options()
+    };
+    let desired_meetings = ::types::read_input_str(&fixtures::test_time_zones_input());
+    options.add_calendar_settings(&desired_meetings);
+    let lon = &options.preferences["lon1@foo.com"];
+    assert_eq!(lon.timezone, Some("Europe/London".to_string()));
+    assert_eq!(lon.working_hours, Some(hours(9, 18)));
+    assert_eq!(options.preferences["sf1@foo.com"].working_hours, Some(hours(8, 17)));
+    assert_eq!(options.preferences["sf1@foo.com"].working_days, vec![1, 2, 3, 4, 5]);
+
+    // Only the slots starting at 16:00, 16:30 and 17:00 UTC are left
+    let input = SolverInput::new_from_desired_meetings_and_opts(desired_meetings, &options);
+    assert_eq!(input.candidate_per_desired_meeting["sync1"].len(), 3);
+    assert_eq!(input.stats["sync1"].rejected_by_preferences, 8);
+}
+
 #[test]
 fn test_new_from_desired_meetings_and_opts() {
     // Create options and fake fetcher
     // Create a desired meetings
     let desired_meetings = fixtures::test_desired_meetings();
     let options = Options {
-        room_picker_fn: Box::new(|_| Some(vec!["room@bar.html".to_string()])),
-        fetch_fn: Box::new(|emails, _, _| fixtures::fetch_results(emails)),
-        consider_meetings_in_the_past: true,
-        ..Default::default()
+        room_picker_fn: Box::new(|_, _| Some(vec!["room@bar.html".to_string()])),
+        ..fixtures::test_options()
     };
     let k = SolverInput::new_from_desired_meetings_and_opts(desired_meetings.clone(), &options);
     // The fetch fn we use returns one meeting for laurent.charignon@foo.com
@@ -149,6 +657,24 @@ fn test_new_from_desired_meetings_and_opts() {
     assert_eq!(k.candidate_per_desired_meeting["title"].len(), 110);
     assert_eq!(k.candidate_per_desired_meeting["title2"].len(), 14);
     assert_eq!(k.candidates.len(), 124);
+    // 13 days of 16 slots, 5 days rejected and 2 slots for lunch on the
+    // remaining 8 days
+    assert_eq!(
+        k.stats["title"],
+        CandidateStats {
+            generated: 208,
+            rejected_by_date: 80,
+            rejected_by_datetime: 16,
+            rejected_as_past: 0,
+            rejected_by_preferences: 0,
+            rejected_by_conflict: 2,
+            rejected_by_room: 0,
+            capped_session_orders: 0,
+            conflicts_per_attendee: vec![("laurent.charignon@foo.com".to_string(), 2)]
+                .into_iter()
+                .collect(),
+        }
+    );
     // 16 intersections because only the first two days overlap
     assert_eq!(k.intersections.len(), 14);
 }
@@ -160,6 +686,10 @@ impl SolverInput {
             candidate_per_desired_meeting: HashMap::new(),
             candidates: HashMap::new(),
             desired_meetings: Vec::new(),
+            best_effort: false,
+            stats: HashMap::new(),
+            limits: Vec::new(),
+            penalties: Vec::new(),
         }
     }
 
@@ -169,88 +699,671 @@ impl SolverInput {
     ) -> SolverInput {
         let mut solver_input = SolverInput::new();
         solver_input.desired_meetings = desired_meetings.clone();
+        solver_input.best_effort = opts.best_effort;
         let emails = extract_attendees(&desired_meetings, &opts.room_picker_fn);
-        let avail: HashMap<String, MeetingsTree> = (opts.fetch_fn)(
+        let avail: HashMap<String, MeetingsTree> = opts.backend.fetch_availability(
             emails,
+            &fetch_window(&desired_meetings),
             opts.ignore_all_day_events,
             opts.ignore_meetings_with_no_response,
         );
         let now = chrono::Utc::now();
         for me in desired_meetings {
-            for interval in gen::generate_all_possible_meetings(
+            let (intervals, mut stats) = gen::generate_all_possible_meetings(
                 &me,
                 &*opts.reject_date_fn,
                 &*opts.reject_datetime_fn,
-            ) {
+            );
+            for interval in intervals {
                 if !opts.consider_meetings_in_the_past && interval.start < now {
+                    stats.rejected_as_past += 1;
                     continue;
                 }
-                if let Some(m) = generate_meeting_candidate(
+                for m in generate_meeting_candidates(
                     &me,
                     &avail,
-                    interval.id.to_string(),
-                    &opts.room_picker_fn,
-                    &opts.scoring_fn,
+                    &interval.id,
+                    opts,
                     &interval,
+                    &mut stats,
                 ) {
-                    solver_input.candidates.insert(interval.id.to_string(), m);
                     solver_input
                         .candidate_per_desired_meeting
                         .entry(me.title.to_string())
                         .or_insert_with(Vec::new)
-                        .push(interval.id.to_string());
+                        .push(m.id.to_string());
+                    solver_input.candidates.insert(m.id.to_string(), m);
                 }
             }
+            solver_input.stats.insert(me.title.to_string(), stats);
         }
-        solver_input.intersections = build_intersections_pairs(&solver_input.candidates);
+        solver_input.intersections =
+            build_intersections_pairs(&solver_input.candidates, &*opts.buffer_fn);
+        solver_input.limits = limits::daily_limit_constraints(
+            &opts.daily_limits,
+            &solver_input.desired_meetings,
+            &solver_input.candidates,
+            &avail,
+        );
+        let (penalties, fairness) = fairness::fairness_constraints(
+            &solver_input.desired_meetings,
+            &solver_input.candidates,
+            &opts.preferences,
+        );
+        solver_input.penalties = penalties;
+        solver_input.limits.extend(fairness);
         solver_input
     }
 
-    fn to_lp_fmt(&self) -> String {
-        let objective_string = format!("  obj: {}", {
-            let mut k = self.candidates
-                .iter()
-                .map(|it| format!("{} {}", it.1.score, it.0))
-                .collect::<Vec<String>>();
-            k.sort();
-            k.join(" + ")
-        });
+    // Build the 0-1 integer linear problem whose solution is the schedule:
+    // one variable per candidate, exactly one candidate per desired meeting
+    // and at most one candidate in every pair of conflicting candidates.
+    // In best effort mode a meeting can have no candidate, but every booked
+    // meeting is worth more than any score difference between schedules.
+    pub fn to_problem(&self) -> Problem {
+        let reward = if self.best_effort {
+            self.meeting_reward()
+        } else {
+            0
+        };
+        let mut objective = self.candidates
+            .iter()
+            .map(|it| (it.1.score as i64 + reward, it.0.to_string()))
+            .collect::<Vec<(i64, String)>>();
 
-        let one_candidate_per_meeting_constraints = {
-            self.candidate_per_desired_meeting
-                .iter()
-                .map(|it| format!("  {} = 1", it.1.join(" + ")))
-                .collect::<Vec<String>>()
+        let relation = if self.best_effort {
+            Relation::Le
+        } else {
+            Relation::Eq
         };
+        let mut constraints = self.candidate_per_desired_meeting
+            .iter()
+            .map(|it| Constraint {
+                terms: it.1.iter().map(|k| (1, k.to_string())).collect(),
+                relation: relation.clone(),
+                rhs: 1,
+            })
+            .collect::<Vec<Constraint>>();
+
+        constraints.extend(self.intersections.iter().map(|it| Constraint {
+            terms: it.iter().map(|k| (1, k.to_string())).collect(),
+            relation: Relation::Le,
+            rhs: 1,
+        }));
+        constraints.extend(self.dependency_constraints());
+        constraints.extend(self.limits.iter().cloned());
+        objective.extend(self.penalties.iter().cloned());
+        let (penalties, balance) = self.pool_balance();
+        objective.extend(penalties);
+        constraints.extend(balance);
 
-        let intersection_constraints = {
-            self.intersections
+        // The candidates and the auxiliary variables of the constraints
+        let mut variables = self.candidates
+            .keys()
+            .map(|k| k.to_string())
+            .chain(objective.iter().map(|k| k.1.to_string()))
+            .chain(constraints.iter().flat_map(|c| c.terms.iter().map(|k| k.1.to_string())))
+            .collect::<Vec<String>>();
+        variables.sort();
+        variables.dedup();
+
+        Problem {
+            objective,
+            constraints,
+            variables,
+        }
+    }
+
+    // A candidate of a meeting that must be after another one can only be
+    // picked if a compatible candidate of the other meeting is picked:
+    // candidate - sum(compatible candidates of the other meeting) <= 0
+    // Nothing constrains a meeting whose predecessor is not in the input, like
+    // when the diagnosis leaves it out.
+    fn dependency_constraints(&self) -> Vec<Constraint> {
+        let mut res = Vec::new();
+        for m in &self.desired_meetings {
+            let after = match m.after {
+                Some(ref after) if self.desired_meetings.iter().any(|k| k.title == after.title) => {
+                    after
+                }
+                _ => continue,
+            };
+            let ids = self.candidate_per_desired_meeting
+                .get(&m.title)
+                .cloned()
+                .unwrap_or_default();
+            let before_ids = self.candidate_per_desired_meeting
+                .get(&after.title)
+                .cloned()
+                .unwrap_or_default();
+            for id in ids {
+                let start = self.candidates[&id].start;
+                let mut terms = vec![(1, id.to_string())];
+                terms.extend(
+                    before_ids
+                        .iter()
+                        .filter(|k| after.allows(self.candidates[*k].end, start))
+                        .map(|k| (-1, k.to_string())),
+                );
+                res.push(Constraint {
+                    terms,
+                    relation: Relation::Le,
+                    rhs: 0,
+                });
+            }
+        }
+        res
+    }
+
+    // Spread the meetings picking from a pool evenly over its members: each
+    // of them gets their share of these meetings, rounded up, and every
+    // meeting above it sets an overload variable which costs more than any
+    // score difference between two candidates:
+    // sum(candidates with the member) - sum(overload variables) <= share
+    // Returns the penalties of the overload variables and the constraints.
+    fn pool_balance(&self) -> (Vec<(i64, String)>, Vec<Constraint>) {
+        // Identical pools of different meetings are shared
+        let mut demand: HashMap<Vec<String>, usize> = HashMap::new();
+        for m in &self.desired_meetings {
+            for p in &m.pools {
+                let mut members = p.members.clone();
+                members.sort();
+                *demand.entry(members).or_insert(0) += p.pick;
+            }
+        }
+        let mut share: HashMap<&str, usize> = HashMap::new();
+        for (members, d) in &demand {
+            for k in members {
+                *share.entry(k.as_str()).or_insert(0) += d.div_ceil(members.len());
+            }
+        }
+        let mut members = share.keys().cloned().collect::<Vec<&str>>();
+        members.sort();
+
+        let penalty = 1 + self.candidates
+            .values()
+            .map(|k| k.score as i64)
+            .max()
+            .unwrap_or(0);
+        let mut penalties = Vec::new();
+        let mut constraints = Vec::new();
+        for (n, member) in members.iter().enumerate() {
+            let meetings = self.desired_meetings
+                .iter()
+                .filter(|m| m.pools.iter().any(|p| p.members.iter().any(|k| k == member)))
+                .collect::<Vec<&DesiredMeeting>>();
+            if meetings.len() <= share[member] {
+                continue;
+            }
+            let mut terms = meetings
+                .iter()
+                .filter_map(|m| self.candidate_per_desired_meeting.get(&m.title))
+                .flat_map(|ids| ids.iter())
+                .filter(|k| self.candidates[*k].attendees.iter().any(|a| a == member))
+                .map(|k| (1, k.to_string()))
+                .collect::<Vec<(i64, String)>>();
+            for j in 0..meetings.len() - share[member] {
+                let var = format!("overload_{}_{}", n, j);
+                terms.push((-1, var.to_string()));
+                penalties.push((-penalty, var));
+            }
+            constraints.push(Constraint {
+                terms,
+                relation: Relation::Le,
+                rhs: share[member] as i64,
+            });
+        }
+        (penalties, constraints)
+    }
+
+    // Desired meetings for which no slot could be found at all
+    pub fn meetings_without_candidates(&self) -> Vec<&DesiredMeeting> {
+        self.desired_meetings
+            .iter()
+            .filter(|m| !self.candidate_per_desired_meeting.contains_key(&m.title))
+            .collect()
+    }
+
+    // Unless we are in best effort mode, a meeting without candidates makes
+    // the problem infeasible. It does not have any variable in the problem so
+    // the solvers would not notice it, hence this check.
+    fn is_satisfiable(&self) -> bool {
+        self.best_effort || self.meetings_without_candidates().is_empty()
+    }
+
+    // Reward for booking a meeting: more than the sum of the best scores of
+    // all the meetings
+    fn meeting_reward(&self) -> i64 {
+        1 + self.candidate_per_desired_meeting
+            .values()
+            .map(|ids| {
+                ids.iter()
+                    .map(|k| self.candidates[k].score as i64)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .sum::<i64>()
+    }
+
+    // Explain why each desired meeting missing from the solution could not
+    // be scheduled
+    pub fn explain_unscheduled(
+        &self,
+        solution: &HashMap<DesiredMeeting, MeetingCandidate>,
+    ) -> HashMap<DesiredMeeting, String> {
+        let booked: HashMap<&str, &str> = solution
+            .values()
+            .map(|c| (c.id.as_str(), c.title.as_str()))
+            .collect();
+        let mut res = HashMap::new();
+        for m in &self.desired_meetings {
+            if solution.contains_key(m) {
+                continue;
+            }
+            let ids = match self.candidate_per_desired_meeting.get(&m.title) {
+                Some(ids) => ids,
+                None => {
+                    let stats = self.stats.get(&m.title).cloned().unwrap_or_default();
+                    res.insert(m.clone(), format!("No possible slot: {}", stats));
+                    continue;
+                }
+            };
+            let mut blocking = self.intersections
                 .iter()
-                .map(|it| format!("  {} <= 1", it.join(" + ")))
-                .collect::<Vec<String>>()
+                .filter(|pair| pair.iter().any(|k| ids.contains(k)))
+                .flat_map(|pair| pair.iter().filter_map(|k| booked.get(k.as_str())))
+                .map(|k| k.to_string())
+                .collect::<HashSet<String>>()
+                .into_iter()
+                .collect::<Vec<String>>();
+            blocking.sort();
+            let reason = match (blocking.is_empty(), m.after.as_ref()) {
+                (true, Some(after)) => format!(
+                    "Must take place after {:?}, which could not be placed accordingly",
+                    after.title
+                ),
+                (true, None) => "Cannot be scheduled without breaking a constraint".to_string(),
+                (false, _) => format!("All its slots conflict with: {}", blocking.join(", ")),
+            };
+            res.insert(m.clone(), reason);
+        }
+        res
+    }
+
+    // Keep only the given desired meetings and their candidates
+    pub fn restricted_to(&self, titles: &HashSet<String>) -> SolverInput {
+        let mut res = SolverInput::new();
+        res.best_effort = self.best_effort;
+        res.desired_meetings = self.desired_meetings
+            .iter()
+            .filter(|k| titles.contains(&k.title))
+            .cloned()
+            .collect();
+        res.candidates = self.candidates
+            .iter()
+            .filter(|k| titles.contains(&k.1.title))
+            .map(|k| (k.0.to_string(), k.1.clone()))
+            .collect();
+        res.candidate_per_desired_meeting = self.candidate_per_desired_meeting
+            .iter()
+            .filter(|k| titles.contains(k.0))
+            .map(|k| (k.0.to_string(), k.1.clone()))
+            .collect();
+        res.intersections = self.intersections
+            .iter()
+            .filter(|pair| pair.iter().all(|k| res.candidates.contains_key(k)))
+            .cloned()
+            .collect();
+        res.stats = self.stats
+            .iter()
+            .filter(|k| titles.contains(k.0))
+            .map(|k| (k.0.to_string(), k.1.clone()))
+            .collect();
+        res.penalties = self.penalties.clone();
+        // Dropped candidates are 0, the limits stay valid without them
+        res.limits = self.limits
+            .iter()
+            .map(|c| Constraint {
+                terms: c.terms
+                    .iter()
+                    .filter(|k| {
+                        res.candidates.contains_key(&k.1) || !self.candidates.contains_key(&k.1)
+                    })
+                    .cloned()
+                    .collect(),
+                ..c.clone()
+            })
+            .collect();
+        res
+    }
+
+    fn to_lp_fmt(&self) -> String {
+        self.to_problem().to_lp_fmt()
+    }
+}
+
+// Relation between the left hand side and the right hand side of a constraint
+#[derive(Debug, Clone, PartialEq)]
+pub enum Relation {
+    Le,
+    Eq,
+}
+
+// A linear constraint: sum(coefficient * variable) <relation> rhs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub terms: Vec<(i64, String)>,
+    pub relation: Relation,
+    pub rhs: i64,
+}
+
+// A 0-1 integer linear problem to maximize, independent of the solver used
+#[derive(Debug)]
+pub struct Problem {
+    pub objective: Vec<(i64, String)>,
+    pub constraints: Vec<Constraint>,
+    pub variables: Vec<String>,
+}
+
+// Format a linear expression, omitting coefficients of 1
+fn terms_to_lp_fmt(terms: &[(i64, String)]) -> String {
+    let mut res = String::new();
+    for (i, &(coef, ref var)) in terms.iter().enumerate() {
+        let term = if coef.abs() == 1 {
+            var.to_string()
+        } else {
+            format!("{} {}", coef.abs(), var)
         };
+        match (i, coef < 0) {
+            (0, false) => res.push_str(&term),
+            (0, true) => res.push_str(&format!("- {}", term)),
+            (_, false) => res.push_str(&format!(" + {}", term)),
+            (_, true) => res.push_str(&format!(" - {}", term)),
+        }
+    }
+    res
+}
 
-        let variables_string = {
-            let mut k = self.candidates
+impl Problem {
+    // Serialize the problem in the LP format understood by cbc
+    pub fn to_lp_fmt(&self) -> String {
+        let objective_string = format!("  obj: {}", {
+            let mut k = self.objective
                 .iter()
-                .map(|it| format!("{}", it.0))
+                .map(|it| terms_to_lp_fmt(slice::from_ref(it)))
                 .collect::<Vec<String>>();
             k.sort();
-            format!("  {}", k.join(" "))
-        };
-        let mut constraints = one_candidate_per_meeting_constraints;
-        constraints.extend(intersection_constraints);
+            k.iter()
+                .enumerate()
+                .map(|(i, t)| match (i, t.starts_with('-')) {
+                    (0, _) => t.to_string(),
+                    (_, true) => format!(" {}", t),
+                    (_, false) => format!(" + {}", t),
+                })
+                .collect::<String>()
+        });
+
+        let mut constraints = self.constraints
+            .iter()
+            .map(|it| {
+                let relation = match it.relation {
+                    Relation::Le => "<=",
+                    Relation::Eq => "=",
+                };
+                format!("  {} {} {}", terms_to_lp_fmt(&it.terms), relation, it.rhs)
+            })
+            .collect::<Vec<String>>();
         constraints.sort();
 
         format!(
-            "Maximize\n{}\nSubject To\n{}\nBinary\n{}\nEnd",
+            "Maximize\n{}\nSubject To\n{}\nBinary\n  {}\nEnd",
             objective_string,
             constraints.join("\n"),
-            variables_string
+            self.variables.join(" ")
         )
     }
 }
 
+#[test]
+fn test_solve_with_builtin_solver() {
+    let (_, solution) = fixtures::solve_input(&fixtures::test_input(), fixtures::test_options());
+    assert_eq!(solution.len(), 2);
+    // Both meetings share an attendee, they cannot overlap
+    let mut picked = solution.values();
+    let (a, b) = (picked.next().unwrap(), picked.next().unwrap());
+    assert!(a.end <= b.start || b.end <= a.start);
+}
+
+#[test]
+fn test_best_effort_books_as_many_meetings_as_possible() {
+    let mut input = fixtures::conflicting_solver_input();
+    assert_eq!(solve_with_builtin_solver(&input), None);
+
+    input.best_effort = true;
+    let solution = solve_with_builtin_solver(&input).expect("Best effort is always feasible");
+    assert_eq!(solution.len(), 1);
+    let unscheduled = input.explain_unscheduled(&solution);
+    assert_eq!(unscheduled.len(), 1);
+    let booked = solution.values().next().unwrap();
+    assert!(unscheduled.values().next().unwrap().contains(&booked.title));
+}
+
+#[test]
+fn test_meeting_without_candidates_is_not_dropped() {
+    let mut input = fixtures::conflicting_solver_input();
+    input.candidates.remove("id0");
+    input.candidate_per_desired_meeting.remove("title2");
+    input.intersections.clear();
+    assert_eq!(input.meetings_without_candidates()[0].title, "title2");
+    assert_eq!(solve_with_builtin_solver(&input), None);
+
+    input.best_effort = true;
+    assert_eq!(solve_with_builtin_solver(&input).map(|k| k.len()), Some(1));
+}
+
+#[test]
+fn test_rooms_are_not_double_booked() {
+    let rooms = vec!["room1@bar.com".to_string(), "room2@bar.com".to_string()];
+    let options = Options {
+        room_picker_fn: Box::new(move |_, _| Some(rooms.clone())),
+        ..fixtures::test_options()
+    };
+    let (input, solution) = fixtures::solve_input(&fixtures::test_parallel_input(), options);
+    // One candidate per room for each meeting
+    assert_eq!(input.candidates.len(), 4);
+
+    let booked_rooms = solution
+        .values()
+        .map(|c| c.room.clone().expect("A room is free"))
+        .collect::<HashSet<String>>();
+    assert_eq!(booked_rooms.len(), 2);
+}
+
+#[test]
+fn test_meeting_without_suitable_room_has_no_candidates() {
+    let options = Options {
+        // Rooms are listed but none of them fits
+        room_picker_fn: Box::new(|_, _| Some(Vec::new())),
+        ..fixtures::test_options()
+    };
+    let input = fixtures::solver_input(&fixtures::test_parallel_input(), &options);
+    assert_eq!(input.candidates.len(), 0);
+    for stats in input.stats.values() {
+        assert!(stats.rejected_by_room > 0);
+    }
+    assert_eq!(input.meetings_without_candidates().len(), 2);
+}
+
+#[test]
+fn test_optional_attendees_only_change_the_score() {
+    let input = fixtures::solver_input(&fixtures::test_optional_input(), &fixtures::test_options());
+    // From 10 to 4 without lunch, the optional attendee being busy from 2 to
+    // 2:45 does not remove any slot
+    assert_eq!(input.candidates.len(), 10);
+    let score_at = |h: u32| {
+        input
+            .candidates
+            .values()
+            .find(|c| c.start.with_timezone(&::chrono_tz::US::Pacific).hour() == h)
+            .map(|c| c.score)
+            .unwrap()
+    };
+    assert!(score_at(14) < score_at(10));
+}
+
+#[test]
+fn test_recurring_meetings_need_free_occurrences() {
+    let input = fixtures::solver_input(&fixtures::test_weekly_input(), &fixtures::test_options());
+    assert!(!input.candidate_per_desired_meeting.contains_key("every week"));
+    assert_eq!(input.candidate_per_desired_meeting["most weeks"].len(), 1);
+    assert_eq!(input.stats["every week"].rejected_by_conflict, 1);
+}
+
+#[test]
+fn test_recurring_candidates_conflict_on_any_occurrence() {
+    let mut candidate_a = fixtures::sample_candidate_a();
+    let mut candidate_b = fixtures::sample_candidate_b();
+    candidate_a.end = candidate_a.start + chrono::Duration::minutes(30);
+    candidate_a.recurrences = vec![
+        (
+            candidate_a.start + chrono::Duration::weeks(1),
+            candidate_a.end + chrono::Duration::weeks(1),
+        ),
+    ];
+    candidate_b.start = candidate_a.recurrences[0].0;
+    candidate_b.end = candidate_a.recurrences[0].1;
+    candidate_b.attendees = candidate_a.attendees.clone();
+
+    let mut candidates = HashMap::new();
+    candidates.insert("id10873".to_string(), candidate_a);
+    candidates.insert("id0".to_string(), candidate_b);
+    assert_eq!(build_intersections_pairs(&candidates, &fixtures::no_buffer).len(), 1);
+}
+
+#[test]
+fn test_meetings_are_booked_in_order() {
+    let (_, solution) =
+        fixtures::solve_input(&fixtures::test_dependent_input(), fixtures::test_options());
+    let start_of = |title: &str| {
+        solution
+            .values()
+            .find(|c| c.title == title)
+            .map(|c| c.start.with_timezone(&::chrono_tz::US::Pacific))
+            .unwrap()
+    };
+    // The review must start at least one hour after the kickoff ends, the only
+    // way to fit both between 10 and 12 is at the start and end of the window
+    assert_eq!(start_of("kickoff").hour(), 10);
+    assert_eq!(start_of("review").hour(), 11);
+    assert_eq!(start_of("review").minute(), 30);
+}
+
+#[test]
+fn test_loop_sessions_follow_interviewers_availability() {
+    let options = Options {
+        room_picker_fn: Box::new(|_, _| Some(vec!["room@bar.com".to_string()])),
+        ..fixtures::test_options()
+    };
+    let (input, solution) = fixtures::solve_input(&fixtures::test_loop_input(), options);
+    // A single start time fits the window and Laurent is busy from 14:00,
+    // so coding has to be the first session
+    assert_eq!(input.candidates.len(), 1);
+    let candidate = solution.values().next().unwrap();
+    assert_eq!(candidate.room, Some("room@bar.com".to_string()));
+    let sessions = candidate
+        .sessions
+        .iter()
+        .map(|s| (s.title.as_str(), s.start.with_timezone(&::chrono_tz::US::Pacific).hour()))
+        .collect::<Vec<(&str, u32)>>();
+    assert_eq!(sessions, vec![("coding", 13), ("design", 14)]);
+}
+
+#[test]
+fn test_session_orders_are_capped() {
+    let mut tm = ::types::read_input_str(&fixtures::test_loop_input()).remove(0);
+    // 4 sessions with free interviewers could go in 24 orders
+    for k in 0..2 {
+        let mut session = tm.sessions[0].clone();
+        session.title = format!("extra{}", k);
+        tm.sessions.push(session);
+    }
+    let avail = tm.sessions
+        .iter()
+        .flat_map(|k| k.interviewers.iter())
+        .map(|k| (k.to_string(), IntervalTree::new()))
+        .collect::<HashMap<String, MeetingsTree>>();
+    let (orders, capped) = session_orders(&tm, tm.min_date, &avail, &Options::default());
+    assert!(capped);
+    assert_eq!(orders.len(), MAX_SESSION_ORDERS);
+    assert_eq!(orders[0], vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn test_loop_interviewers_are_not_double_booked() {
+    let mut candidate_a = fixtures::sample_candidate_a();
+    let mut candidate_b = fixtures::sample_candidate_b();
+    candidate_a.end = candidate_a.start + chrono::Duration::hours(1);
+    candidate_b.start = candidate_a.start;
+    candidate_b.end = candidate_a.end;
+    let start = candidate_a.start;
+    let session = |title: &str, minutes: i64| SessionSlot {
+        title: title.to_string(),
+        interviewers: vec!["interviewer@foo.com".to_string()],
+        start: start + chrono::Duration::minutes(minutes),
+        end: start + chrono::Duration::minutes(minutes + 30),
+    };
+    // The interviewer is only held for their own session
+    candidate_a.sessions = vec![session("coding", 0)];
+    candidate_b.sessions = vec![session("design", 30)];
+    let mut candidates = HashMap::new();
+    candidates.insert("id10873".to_string(), candidate_a.clone());
+    candidates.insert("id0".to_string(), candidate_b.clone());
+    assert_eq!(build_intersections_pairs(&candidates, &fixtures::no_buffer).len(), 0);
+
+    candidate_b.sessions = vec![session("design", 15)];
+    candidates.insert("id0".to_string(), candidate_b);
+    assert_eq!(build_intersections_pairs(&candidates, &fixtures::no_buffer).len(), 1);
+}
+
+#[test]
+fn test_pool_members_are_picked_by_availability_and_balanced() {
+    let (input, solution) =
+        fixtures::solve_input(&fixtures::test_pool_input(), fixtures::test_options());
+    // 6 slots with either member, except Laurent at 14:00 and 14:30
+    assert_eq!(input.candidate_per_desired_meeting["review1"].len(), 10);
+
+    let mut picked = solution
+        .values()
+        .map(|c| c.attendees[1].to_string())
+        .collect::<Vec<String>>();
+    picked.sort();
+    // Laurent would be the best pick for both, but the load is balanced
+    assert_eq!(
+        picked,
+        vec!["contact@laurent.com", "laurent.charignon@foo.com"]
+    );
+}
+
+#[test]
+fn test_pools_never_pick_someone_twice() {
+    let options = fixtures::test_options();
+    // Both pools of review1 have the same members
+    let pool = "  - pick: 1
+    from:
+    - laurent.charignon@foo.com
+    - contact@laurent.com
+";
+    let input_str = fixtures::test_pool_input().replacen(pool, &format!("{}{}", pool, pool), 1);
+    let input = fixtures::solver_input(&input_str, &options);
+    let candidates = &input.candidate_per_desired_meeting["review1"];
+    assert!(!candidates.is_empty());
+    for id in candidates {
+        let attendees = &input.candidates[id].attendees;
+        assert_eq!(attendees.len(), 3);
+        assert!(attendees[1] != attendees[2]);
+    }
+}
+
 #[test]
 fn test_to_lp_fmt() {
     let mut input = SolverInput::new();
@@ -306,13 +1419,23 @@ fn read_cbc_solver_solution(
     let score = -k;
     println!("Total score is {}", score);
 
+    let chosen = lines
+        .map(|l| l.split_whitespace().collect::<Vec<&str>>())
+        .filter(|words| words[2] == "1")
+        .map(|words| words[1].to_string())
+        .collect::<HashSet<String>>();
+    Some(chosen_candidates_to_meetings(&chosen, solver_input))
+}
+
+// Map the candidates picked by a solver to the desired meetings they satisfy,
+// variables that are not candidates are ignored
+fn chosen_candidates_to_meetings(
+    chosen: &HashSet<String>,
+    solver_input: &SolverInput,
+) -> HashMap<DesiredMeeting, MeetingCandidate> {
     let mut res: HashMap<DesiredMeeting, MeetingCandidate> = HashMap::new();
-    for l in lines {
-        let words: Vec<&str> = l.split_whitespace().collect();
-        let ident = words[1];
-        let val = words[2];
-        if val == "1" {
-            let candidate = &solver_input.candidates[ident];
+    for ident in chosen {
+        if let Some(candidate) = solver_input.candidates.get(ident) {
             let desired_meeting = solver_input
                 .desired_meetings
                 .iter()
@@ -324,7 +1447,7 @@ fn read_cbc_solver_solution(
             res.insert(desired_meeting.clone(), candidate.clone());
         }
     }
-    Some(res)
+    res
 }
 
 #[test]
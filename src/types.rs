@@ -1,3 +1,4 @@
+use std::cmp;
 use std::hash::{Hash, Hasher};
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -8,39 +9,51 @@ use std::fs::File;
 use bio::data_structures::interval_tree::IntervalTree;
 use chrono::prelude::*;
 use chrono;
-use gcal;
+use backend;
+use backend::CalendarBackend;
 use gen;
+use ical;
+use limits;
 use serde_yaml;
 use solver;
 use chrono_tz::Tz;
 use std::fmt;
-use fixtures::{test_config, test_input, test_invalid_input};
+use std::ops::Range;
+use fixtures::{fetch_results, test_config, test_dependent_input, test_desired_meeting, test_input,
+               test_invalid_input, test_loop_input, test_preferences, test_recurring_input,
+               test_rooms_config, test_scoring_config, test_time_zones_preferences};
 pub type ScoringFnType = Box<
-    Fn(&chrono::DateTime<Utc>, &chrono::DateTime<Utc>, &[String], &HashMap<String, MeetingsTree>)
-        -> usize,
+    Fn(
+        &DesiredMeeting,
+        &chrono::DateTime<Utc>,
+        &chrono::DateTime<Utc>,
+        &[String],
+        &HashMap<String, MeetingsTree>,
+    ) -> usize,
 >;
 pub type RejectDateTimeFnType = Box<Fn(chrono::DateTime<Tz>, chrono::DateTime<Tz>) -> bool>;
 pub type RejectDateFnType = Box<Fn(chrono::Date<Tz>) -> bool>;
-pub type RoomPickerFnType = Box<Fn(usize) -> Option<Vec<String>>>;
+pub type RoomPickerFnType = Box<Fn(usize, &[String]) -> Option<Vec<String>>>;
 pub type SolverFnType =
     Box<Fn(&solver::SolverInput) -> Option<HashMap<DesiredMeeting, MeetingCandidate>>>;
-pub type FetchFnType = Box<Fn(Vec<String>, bool, bool) -> HashMap<String, MeetingsTree>>;
+pub type BufferFnType = Box<Fn(&str) -> chrono::Duration>;
 
 // Options is a struct to represent all the tweakable part of the workflow
 // it can be used to modify the behavior of the whole program for example by
 // swapping scoring functions, fetching strategy or room picking algorithm.
 // It should we built at the high level from the user input.
 pub struct Options {
-    // How to fetch the meetings from the API
-    // Default: fetching in // with google calendar
-    pub fetch_fn: FetchFnType,
+    // Where the calendars are read and the meetings booked
+    // Default: google calendar, fetching in // the events of the calendars
+    pub backend: Box<CalendarBackend>,
 
     // How to solve the problem
-    // Default: use a CBC solver
+    // Default: use a CBC solver if cbc is installed, the builtin solver otherwise
     pub solver_fn: SolverFnType,
 
-    // Given the size of a meeting returns a list of email addresses of rooms where it
-    // could happen.
+    // Given the size of a meeting and the tags its room needs returns a list of email
+    // addresses of rooms where it could happen, in order of preference. The solver
+    // picks among the free ones.
     // Default: no room booked
     pub room_picker_fn: RoomPickerFnType,
 
@@ -71,54 +84,181 @@ pub struct Options {
     // includes time in the past, this is mostly useful for testing and should
     // generally be false (default value)
     pub consider_meetings_in_the_past: bool,
+
+    // If true, meetings that cannot be scheduled are left out instead of
+    // making the whole problem infeasible, the solver books as many meetings
+    // as possible
+    // Default: false
+    pub best_effort: bool,
+
+    // Per person limits on meeting time and focus time
+    // Default: none
+    pub daily_limits: Vec<DailyLimit>,
+
+    // Free time to keep around the meetings of an attendee or a room,
+    // against their existing meetings as well as the ones being scheduled
+    // Default: no buffer
+    pub buffer_fn: BufferFnType,
+
+    // Time preferences of the attendees, by email
+    // Default: none
+    pub preferences: HashMap<String, Preferences>,
+
+    // Working hours of the attendees whose calendar does not tell, in the
+    // timezone of their calendar. The calendars are only read if it is set
+    // Default: None
+    pub calendar_working_hours: Option<HourRange>,
+}
+
+impl Options {
+    // Complete the preferences of the attendees with what their calendar
+    // says, what the preferences file says wins
+    pub fn add_calendar_settings(&mut self, desired_meetings: &[DesiredMeeting]) {
+        let default_hours = match self.calendar_working_hours {
+            Some(ref h) => h.clone(),
+            None => return,
+        };
+        let settings = self.backend.fetch_settings(
+            limits::everyone(desired_meetings),
+            &solver::fetch_window(desired_meetings),
+        );
+        for (who, s) in settings {
+            let p = self.preferences.entry(who).or_default();
+            if p.timezone.is_none() {
+                p.timezone = Some(s.timezone.name().to_string());
+            }
+            if p.working_hours.is_none() {
+                p.working_hours = Some(s.working_hours.unwrap_or_else(|| default_hours.clone()));
+            }
+            if p.working_days.is_empty() {
+                p.working_days = s.working_days;
+            }
+        }
+    }
 }
 
 impl Default for Options {
     fn default() -> Self {
         Options {
-            fetch_fn: Box::new(gcal::fetch_availability_with_api),
-            solver_fn: Box::new(solver::solve_with_cbc_solver),
-            scoring_fn: Box::new(compute_score),
+            backend: backend::backend_from_config(None, false),
+            solver_fn: solver::solver_from_name(None),
+            scoring_fn: Box::new(|m, start, end, attendees, availability| {
+                Scoring::default().score(m, start, end, attendees, availability)
+            }),
             ignore_all_day_events: default_ignore_all_day_events(),
             ignore_meetings_with_no_response: default_ignore_meetings_with_no_response(),
             consider_meetings_in_the_past: false,
-            room_picker_fn: Box::new(|_| None),
+            best_effort: false,
+            daily_limits: Vec::new(),
+            buffer_fn: Box::new(|_| chrono::Duration::zero()),
+            preferences: HashMap::new(),
+            calendar_working_hours: None,
+            room_picker_fn: Box::new(|_, _| None),
             reject_date_fn: Box::new(gen::default_reject_date),
             reject_datetime_fn: Box::new(gen::default_reject_datetime),
         }
     }
 }
 
-// Compute the score for a slot given list of attendees and their availability
-// Can be better
-fn compute_score(
-    start: &chrono::DateTime<chrono::Utc>,
-    end: &chrono::DateTime<chrono::Utc>,
-    attendees: &[String],
-    availability: &HashMap<String, MeetingsTree>,
-) -> usize {
-    let mut score = 1;
-
-    // TODO Need to add score based on start of the range
-    for a in attendees.iter() {
-        // Get all the meetings in the two hour range. Each of them is worth 20
-        score += availability[a]
-            .find(*start - chrono::Duration::hours(2)..*end + chrono::Duration::hours(2))
-            .count() * 20;
-        // Get all the meetings in the 1 hour range. Each of them is worth 100
-        score += availability[a]
-            .find(*start - chrono::Duration::hours(1)..*end + chrono::Duration::hours(1))
-            .count() * 100;
-        // Get all the meetings in the 30 min range. Each of them is worth 300
-        score += availability[a]
-            .find(*start - chrono::Duration::minutes(30)..*end + chrono::Duration::minutes(30))
-            .count() * 300;
-        // Get all the meetings in the 15 min range. Each of them is worth 600
-        score += availability[a]
-            .find(*start - chrono::Duration::minutes(15)..*end + chrono::Duration::minutes(15))
-            .count() * 600;
-    }
-    score
+// Points for every existing meeting of an attendee taking place within
+// minutes of the slot, windows add up so closer meetings are worth more
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ClusteringWeight {
+    pub within_minutes: i64,
+    pub points: usize,
+}
+
+// Points for slots starting between from and to
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct PreferredHours {
+    pub from: HourSpec,
+    pub to: HourSpec,
+    pub points: usize,
+}
+
+// How slots are scored, the solver picks the schedule with the best total.
+// The default favors slots close to the existing meetings of the attendees to
+// avoid fragmenting their day.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Scoring {
+    pub clustering: Vec<ClusteringWeight>,
+    // In the timezone of the meeting
+    pub preferred_hours: Vec<PreferredHours>,
+    // Points for every day between the slot and the last day the meeting can
+    // take place, the higher the sooner the meeting
+    pub earlier_date_points: usize,
+    // Points lost by the first and last slots of the day
+    pub first_slot_penalty: usize,
+    pub last_slot_penalty: usize,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        let weight = |within_minutes, points| ClusteringWeight {
+            within_minutes,
+            points,
+        };
+        Scoring {
+            clustering: vec![weight(120, 20), weight(60, 100), weight(30, 300), weight(15, 600)],
+            preferred_hours: Vec::new(),
+            earlier_date_points: 0,
+            first_slot_penalty: 0,
+            last_slot_penalty: 0,
+        }
+    }
+}
+
+impl Scoring {
+    // Score of a slot of the desired meeting given the list of attendees and
+    // their availability
+    pub fn score(
+        &self,
+        m: &DesiredMeeting,
+        start: &chrono::DateTime<chrono::Utc>,
+        end: &chrono::DateTime<chrono::Utc>,
+        attendees: &[String],
+        availability: &HashMap<String, MeetingsTree>,
+    ) -> usize {
+        let mut score = 1;
+        for a in attendees.iter() {
+            for w in &self.clustering {
+                let within = chrono::Duration::minutes(w.within_minutes);
+                score += availability[a].find(*start - within..*end + within).count() * w.points;
+            }
+        }
+
+        let local_start = start.with_timezone(&m.timezone).time();
+        for p in &self.preferred_hours {
+            let from = chrono::NaiveTime::from_hms(p.from.hours, p.from.minutes, p.from.seconds);
+            let to = chrono::NaiveTime::from_hms(p.to.hours, p.to.minutes, p.to.seconds);
+            if local_start >= from && local_start < to {
+                score += p.points;
+            }
+        }
+
+        let days_left = m.max_date.signed_duration_since(*start).num_days();
+        score += self.earlier_date_points * cmp::max(0, days_left) as usize;
+
+        // The day of a meeting goes from the time of day of min_date to the
+        // one of max_date, the next day if that one is earlier like midnight
+        let day_start = m.min_date.with_timezone(&m.timezone).time();
+        let day_end = m.max_date.with_timezone(&m.timezone).time();
+        let mut first_day = start.with_timezone(&m.timezone).date().naive_local();
+        if day_end <= day_start && local_start < day_start {
+            first_day = first_day.pred();
+        }
+        let last_day = if day_end <= day_start { first_day.succ() } else { first_day };
+        let day = from_local_time(&m.timezone, &first_day.and_time(day_start))
+            ..from_local_time(&m.timezone, &last_day.and_time(day_end));
+        if *start < day.start + m.step {
+            score = score.saturating_sub(self.first_slot_penalty);
+        }
+        if *end > day.end - m.step {
+            score = score.saturating_sub(self.last_slot_penalty);
+        }
+        score
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -134,14 +274,237 @@ pub struct HourRange {
     pub to: HourSpec,
 }
 
-// Config holds rooms available to book, small means 2 people or less
-// large 3+ people
+// Limits on the meetings of some people, checked for every day they have
+// candidates, their existing meetings count too
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct DailyLimit {
+    // Default: everyone invited to the meetings to schedule
+    pub attendees: Option<Vec<String>>,
+    // At most this many minutes in meetings per day
+    pub max_meeting_minutes: Option<i64>,
+    // Keep a free block of at least this many minutes every day
+    pub min_focus_minutes: Option<i64>,
+    // When the free block can be, default: 9 to 18
+    pub working_hours: Option<HourRange>,
+}
+
+// Points lost by a slot for every attendee who would rather not meet that day
+const DISLIKED_DAY_PENALTY: usize = 300;
+// A slot starting or ending this close to the edges of the working hours of
+// an attendee is early or late for them
+const INCONVENIENT_MINUTES: i64 = 60;
+
+// What the calendar of a person tells about when they work
+#[derive(Debug, PartialEq, Clone)]
+pub struct CalendarSettings {
+    pub timezone: Tz,
+    // Not every calendar has them
+    pub working_hours: Option<HourRange>,
+    // ISO weekdays, empty when the calendar does not tell
+    pub working_days: Vec<u32>,
+}
+
+// When a person likes to meet, hours are in their timezone if they have one
+// and in the timezone of the meeting otherwise
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Preferences {
+    pub timezone: Option<String>,
+    // No meeting outside of these hours, default: any time
+    pub working_hours: Option<HourRange>,
+    // ISO weekdays to meet on, default: any day
+    pub working_days: Vec<u32>,
+    // Points for slots starting in these hours
+    pub preferred_hours: Vec<PreferredHours>,
+    // ISO weekdays to avoid if possible
+    pub disliked_days: Vec<u32>,
+    // Never book a meeting overlapping these hours
+    pub no_meeting_hours: Vec<HourRange>,
+}
+
+// The instant of a local time. A time skipped by a DST change is shifted
+// forward by the length of the gap, like RFC 5545 does, so the start of a
+// gap is the first instant after it.
+pub fn from_local_time(tz: &Tz, t: &chrono::NaiveDateTime) -> DateTime<chrono::Utc> {
+    if let Some(k) = tz.from_local_datetime(t).earliest() {
+        return k.with_timezone(&chrono::Utc);
+    }
+    // Read with the offset in effect right before the gap
+    let offset = (1..)
+        .filter_map(|k| tz.from_local_datetime(&(*t - chrono::Duration::minutes(k))).earliest())
+        .map(|k| k.offset().fix().local_minus_utc())
+        .next()
+        .expect("A DST gap ends");
+    chrono::Utc.from_utc_datetime(&(*t - chrono::Duration::seconds(offset as i64)))
+}
+
+fn to_naive_time(h: &HourSpec) -> chrono::NaiveTime {
+    chrono::NaiveTime::from_hms(h.hours, h.minutes, h.seconds)
+}
+
+// The instants of some hours starting on a local date, they end the next day
+// when they wrap around midnight like 22:00-06:00
+fn hours_on(tz: &Tz, h: &HourRange, day: chrono::NaiveDate) -> Range<DateTime<chrono::Utc>> {
+    let (from, to) = (to_naive_time(&h.from), to_naive_time(&h.to));
+    let end_day = if to <= from { day.succ() } else { day };
+    from_local_time(tz, &day.and_time(from))..from_local_time(tz, &end_day.and_time(to))
+}
+
+impl Preferences {
+    fn local_tz(&self, meeting_tz: &Tz) -> Tz {
+        match self.timezone {
+            Some(ref k) => k.parse().expect("Cannot parse timezone!"),
+            None => *meeting_tz,
+        }
+    }
+
+    pub fn accepts(&self, tz: &Tz, start: DateTime<chrono::Utc>, end: DateTime<chrono::Utc>) -> bool {
+        let tz = self.local_tz(tz);
+        let local_start = start.with_timezone(&tz);
+        let day = local_start.weekday().number_from_monday();
+        if !self.working_days.is_empty() && !self.working_days.contains(&day) {
+            return false;
+        }
+        // Hours wrapping around midnight can start the day before
+        let first_day = local_start.date().naive_local().pred();
+        if let Some(ref h) = self.working_hours {
+            if !(0..2)
+                .map(|k| hours_on(&tz, h, first_day + chrono::Duration::days(k)))
+                .any(|w| w.start <= start && end <= w.end)
+            {
+                return false;
+            }
+        }
+        // A meeting can span midnight, check the hours of every day it meets
+        let days = (end.with_timezone(&tz).date().naive_local().signed_duration_since(first_day))
+            .num_days();
+        !self.no_meeting_hours.iter().any(|h| {
+            (0..days + 1)
+                .map(|k| hours_on(&tz, h, first_day + chrono::Duration::days(k)))
+                .any(|w| start < w.end && end > w.start)
+        })
+    }
+
+    // Is the slot at the very start or end of the working hours, 9 to 18 if
+    // not set?
+    pub fn is_inconvenient(&self, tz: &Tz, start: DateTime<chrono::Utc>, end: DateTime<chrono::Utc>) -> bool {
+        let tz = self.local_tz(tz);
+        let office_hours = HourRange {
+            from: HourSpec { hours: 9, minutes: 0, seconds: 0 },
+            to: HourSpec { hours: 18, minutes: 0, seconds: 0 },
+        };
+        let h = self.working_hours.as_ref().unwrap_or(&office_hours);
+        let first_day = start.with_timezone(&tz).date().naive_local().pred();
+        let edge = chrono::Duration::minutes(INCONVENIENT_MINUTES);
+        // Hours wrapping around midnight can start the day before, a slot
+        // starting outside of them is inconvenient too
+        match (0..2)
+            .map(|k| hours_on(&tz, h, first_day + chrono::Duration::days(k)))
+            .find(|w| w.start <= start && start < w.end)
+        {
+            Some(w) => start < w.start + edge || end > w.end - edge,
+            None => true,
+        }
+    }
+
+    pub fn bonus(&self, tz: &Tz, start: DateTime<chrono::Utc>) -> usize {
+        let time = start.with_timezone(&self.local_tz(tz)).time();
+        self.preferred_hours
+            .iter()
+            .filter(|p| time >= to_naive_time(&p.from) && time < to_naive_time(&p.to))
+            .map(|p| p.points)
+            .sum()
+    }
+
+    pub fn penalty(&self, tz: &Tz, start: DateTime<chrono::Utc>) -> usize {
+        let day = start.with_timezone(&self.local_tz(tz)).weekday().number_from_monday();
+        if self.disliked_days.contains(&day) {
+            DISLIKED_DAY_PENALTY
+        } else {
+            0
+        }
+    }
+}
+
+// Minutes to keep free before and after the meetings of a person or a room,
+// rooms can use it for cleanup
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Buffers {
+    // For everyone and every room not listed below, default: 0
+    pub default_minutes: Option<i64>,
+    #[serde(default)]
+    pub attendees: HashMap<String, i64>,
+    #[serde(default)]
+    pub rooms: HashMap<String, i64>,
+}
+
+// A room that can be booked, tags describe what it offers (video,
+// whiteboard, building:hq, floor:2, ...)
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Room {
+    pub email: String,
+    // Number of seats, unknown if not set
+    pub capacity: Option<usize>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// Where the calendars are on a CalDAV server
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct CalDavConfig {
+    // Calendar of every person, {email} is replaced by their address
+    pub calendar_url: String,
+    // Calendar the meetings are booked in
+    pub booking_url: String,
+    // Address the meetings are organized by, the server invites the
+    // attendees on its behalf
+    pub organizer: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+// Where the calendar files of everyone are
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct IcsConfig {
+    // Holds a file named after each person, like me@foo.com.ics
+    pub directory: Option<String>,
+    // The file of each person, for those not in the directory. None for the
+    // people without a calendar, like guests, who are taken as free
+    #[serde(default)]
+    pub files: HashMap<String, Option<String>>,
+}
+
+// Whose calendar on microsoft graph books the meetings
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct GraphConfig {
+    pub mailbox: String,
+    // Default: the GRAPH_ACCESS_TOKEN environment variable
+    pub access_token: Option<String>,
+    // Default: https://graph.microsoft.com/v1.0/
+    pub base_url: Option<String>,
+}
+
+// Config holds rooms available to book. small_rooms and large_rooms are the
+// legacy way to list them, small means 2 people or less large 3+ people
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Config {
+    pub rooms: Option<Vec<Room>>,
     pub small_rooms: Option<Vec<String>>,
     pub large_rooms: Option<Vec<String>>,
     pub reject_iso_weekday: Option<Vec<u32>>,
     pub reject_hour_range: Option<Vec<HourRange>>,
+    pub daily_limits: Option<Vec<DailyLimit>>,
+    pub buffers: Option<Buffers>,
+    pub scoring: Option<Scoring>,
+    pub calendar_working_hours: Option<HourRange>,
+    // Calendar provider, google by default
+    pub backend: Option<String>,
+    pub caldav: Option<CalDavConfig>,
+    pub graph: Option<GraphConfig>,
+    pub ics: Option<IcsConfig>,
+    // Also book the rooms the calendar provider knows about
+    #[serde(default)]
+    pub discover_rooms: bool,
     #[serde(default = "default_ignore_all_day_events")]
     pub ignore_all_day_events: bool,
     #[serde(default = "default_ignore_meetings_with_no_response")]
@@ -160,12 +523,59 @@ impl Config {
         serde_yaml::from_str(&s).expect("Cannot decode YAML")
     }
 
-    pub fn room_picker(&self, size: usize) -> Option<Vec<String>> {
+    // The rooms listed for a meeting of this size. The legacy small rooms
+    // have 2 seats and are the only legacy rooms of meetings of 2 people or
+    // less, the size of the large rooms is unknown.
+    fn all_rooms(&self, size: usize) -> Vec<Room> {
+        let legacy = |rooms: &Option<Vec<String>>, capacity: Option<usize>| {
+            rooms
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |email| Room {
+                    email,
+                    capacity,
+                    tags: Vec::new(),
+                })
+        };
+        let mut res = self.rooms.clone().unwrap_or_default();
         if size <= 2 {
-            self.small_rooms.clone()
+            res.extend(legacy(&self.small_rooms, Some(2)));
         } else {
-            self.large_rooms.clone()
+            res.extend(legacy(&self.large_rooms, None));
         }
+        res
+    }
+
+    // Rooms with enough seats and all the required tags, smallest first.
+    // None when no room is listed for the size, the meeting needs no room
+    // then. Empty when none of the rooms listed fits.
+    pub fn room_picker(&self, size: usize, tags: &[String]) -> Option<Vec<String>> {
+        let rooms = self.all_rooms(size);
+        if rooms.is_empty() {
+            return None;
+        }
+        let mut rooms = rooms
+            .into_iter()
+            .filter(|r| r.capacity.is_none_or(|c| c >= size))
+            .filter(|r| tags.iter().all(|t| r.tags.contains(t)))
+            .collect::<Vec<Room>>();
+        rooms.sort_by_key(|r| r.capacity.unwrap_or(usize::MAX));
+        Some(rooms.into_iter().map(|r| r.email).collect())
+    }
+
+    // Buffer to keep around the meetings of an attendee or a room
+    pub fn buffer(&self, who: &str) -> chrono::Duration {
+        let minutes = match self.buffers {
+            Some(ref b) => b.attendees
+                .get(who)
+                .or_else(|| b.rooms.get(who))
+                .cloned()
+                .or(b.default_minutes)
+                .unwrap_or(0),
+            None => 0,
+        };
+        chrono::Duration::minutes(minutes)
     }
 
     pub fn reject_date_fn(&self, d: chrono::Date<Tz>) -> bool {
@@ -225,7 +635,49 @@ pub struct MeetingCandidate {
     pub start: DateTime<chrono::Utc>,
     pub end: DateTime<chrono::Utc>,
     pub room: Option<String>,
+    pub attendees: Vec<String>,
     pub score: usize,
+    // Start and end of the following occurrences of a recurring meeting
+    pub recurrences: Vec<(DateTime<chrono::Utc>, DateTime<chrono::Utc>)>,
+    // Sessions of an interview loop, in the order they take place
+    pub sessions: Vec<SessionSlot>,
+}
+
+// A session of an interview loop placed in its sub-slot of the candidate
+#[derive(Clone, PartialEq, Debug)]
+pub struct SessionSlot {
+    pub title: String,
+    pub interviewers: Vec<String>,
+    pub start: DateTime<chrono::Utc>,
+    pub end: DateTime<chrono::Utc>,
+}
+
+impl MeetingCandidate {
+    // Time ranges taken by the candidate, one per occurrence
+    pub fn intervals(&self) -> Vec<Range<DateTime<chrono::Utc>>> {
+        let mut res = vec![self.start..self.end];
+        res.extend(self.recurrences.iter().map(|k| k.0..k.1));
+        res
+    }
+
+    // Everything this candidate holds and when: its attendees and the room it
+    // would be booked in for every occurrence, and the interviewers of a loop
+    // for their session only. Two candidates only conflict if they hold the
+    // same resource at the same time.
+    pub fn reservations(&self) -> Vec<(String, Range<DateTime<chrono::Utc>>)> {
+        let mut resources = self.attendees.clone();
+        if let Some(room) = self.room.clone() {
+            resources.push(room);
+        }
+        let mut res = Vec::new();
+        for range in self.intervals() {
+            res.extend(resources.iter().map(|r| (r.to_string(), range.clone())));
+        }
+        for s in &self.sessions {
+            res.extend(s.interviewers.iter().map(|r| (r.to_string(), s.start..s.end)));
+        }
+        res
+    }
 }
 
 impl fmt::Debug for MeetingCandidate {
@@ -238,7 +690,18 @@ impl fmt::Debug for MeetingCandidate {
             self.end.with_timezone(&chrono::Local).to_string(),
             self.room,
             self.score
-        )
+        )?;
+        for s in &self.sessions {
+            write!(
+                f,
+                "\n    {:?} from {} to {} with {}",
+                s.title,
+                s.start.with_timezone(&chrono::Local),
+                s.end.with_timezone(&chrono::Local),
+                s.interviewers.join(", ")
+            )?;
+        }
+        Ok(())
     }
 }
 impl fmt::Debug for Solution {
@@ -249,7 +712,51 @@ impl fmt::Debug for Solution {
             .map(|it| format!("{:?}\n >>> {:?}\n", it.0, it.1))
             .collect::<Vec<String>>()
             .join("===============================\n");
-        write!(f, "{}\n{}\n", solved, candidates_str)
+        let unscheduled_str = self.unscheduled
+            .iter()
+            .map(|it| format!("Not scheduled: {:?}\n >>> {}\n", it.0.title, it.1))
+            .collect::<String>();
+        write!(f, "{}\n{}\n{}", solved, candidates_str, unscheduled_str)
+    }
+}
+
+// Counts of the slots considered for a desired meeting that did not become
+// candidates, and why
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CandidateStats {
+    // All the slots considered, whether they were rejected or not
+    pub generated: usize,
+    pub rejected_by_date: usize,
+    pub rejected_by_datetime: usize,
+    pub rejected_as_past: usize,
+    // Outside the working hours or in the no meeting hours of an attendee
+    pub rejected_by_preferences: usize,
+    pub rejected_by_conflict: usize,
+    // Rooms are listed but none has enough seats and the tags asked for
+    pub rejected_by_room: usize,
+    // Slots of an interview loop where only some of the possible orders of
+    // its sessions were kept
+    pub capped_session_orders: usize,
+    // Number of slots each attendee was busy for
+    pub conflicts_per_attendee: HashMap<String, usize>,
+}
+
+impl fmt::Display for CandidateStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} slots considered, {} rejected by date, {} rejected by time of day, \
+             {} in the past, {} refused by attendees, {} with busy attendees, \
+             {} without a suitable room, {} with session orders left out",
+            self.generated,
+            self.rejected_by_date,
+            self.rejected_by_datetime,
+            self.rejected_as_past,
+            self.rejected_by_preferences,
+            self.rejected_by_conflict,
+            self.rejected_by_room,
+            self.capped_session_orders
+        )
     }
 }
 
@@ -268,6 +775,8 @@ pub type MeetingsTree = IntervalTree<DateTime<chrono::Utc>, String>;
 pub struct Solution {
     pub solved: bool,
     pub candidates: HashMap<DesiredMeeting, MeetingCandidate>,
+    // Meetings left out in best effort mode, with the reason why
+    pub unscheduled: HashMap<DesiredMeeting, String>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -275,11 +784,123 @@ struct InputDesiredMeeting {
     title: String,
     description: String,
     attendees: Vec<String>,
+    #[serde(default)]
+    optional_attendees: Vec<String>,
     min_date: chrono::NaiveDateTime,
     max_date: chrono::NaiveDateTime,
     step: Option<i64>,
     duration: Option<i64>,
     timezone: Option<String>,
+    #[serde(default)]
+    room_tags: Vec<String>,
+    recurrence: Option<InputRecurrence>,
+    // Title of a meeting this one must take place after, with the minimum and
+    // maximum number of minutes between the end of that meeting and the start
+    // of this one
+    after: Option<String>,
+    min_gap: Option<i64>,
+    max_gap: Option<i64>,
+    // Sessions of an interview loop, the attendees take part in all of them
+    #[serde(default)]
+    sessions: Vec<InputSession>,
+    #[serde(default)]
+    pools: Vec<InputPool>,
+}
+
+// Any `pick` of the people listed in `from`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct InputPool {
+    pick: usize,
+    from: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct InputSession {
+    title: String,
+    interviewers: Vec<String>,
+    // In minutes, default: the duration of the meeting
+    duration: Option<i64>,
+}
+
+// Either a number of weeks or a RRULE supporting FREQ (DAILY or WEEKLY),
+// INTERVAL and COUNT
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct InputRecurrence {
+    weeks: Option<usize>,
+    rrule: Option<String>,
+    // Fraction of the occurrences where all the attendees must be free
+    // Default: 1, every occurrence
+    min_free_fraction: Option<f64>,
+}
+
+// How a desired meeting repeats, every occurrence takes place at the same
+// time of day, period days after the previous one
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Recurrence {
+    pub period: chrono::Duration,
+    pub count: usize,
+    // Number of occurrences that must have all the attendees available
+    pub min_free_occurrences: usize,
+}
+
+impl Recurrence {
+    fn from_input(i: &InputRecurrence) -> Recurrence {
+        let (period, count) = match (i.weeks, i.rrule.as_ref()) {
+            (Some(weeks), None) => (chrono::Duration::weeks(1), weeks),
+            (None, Some(rrule)) => parse_rrule(rrule),
+            _ => panic!("A recurrence needs exactly one of weeks or rrule"),
+        };
+        if count < 1 {
+            panic!("A recurrence needs at least one occurrence");
+        }
+        if period <= chrono::Duration::zero() {
+            panic!("The RRULE INTERVAL must be at least 1");
+        }
+        let fraction = i.min_free_fraction.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&fraction) {
+            panic!("min_free_fraction must be between 0 and 1");
+        }
+        Recurrence {
+            period,
+            count,
+            min_free_occurrences: (fraction * count as f64).ceil() as usize,
+        }
+    }
+
+    // RRULE to give to the calendar when booking the meeting
+    pub fn to_rrule(&self) -> String {
+        let days = self.period.num_days();
+        if days % 7 == 0 {
+            format!("RRULE:FREQ=WEEKLY;INTERVAL={};COUNT={}", days / 7, self.count)
+        } else {
+            format!("RRULE:FREQ=DAILY;INTERVAL={};COUNT={}", days, self.count)
+        }
+    }
+}
+
+// Parse the subset of RRULE we support into a period and a count. Where
+// the occurrences land depends on the slot picked, so the rule cannot pick
+// days nor end at a date.
+fn parse_rrule(rrule: &str) -> (chrono::Duration, usize) {
+    let rule = ical::parse_rule(rrule, &Tz::UTC)
+        .unwrap_or_else(|k| panic!("Cannot read the RRULE {}: {}", rrule, k));
+    let days = match rule.freq.as_str() {
+        "DAILY" => 1,
+        "WEEKLY" => 7,
+        _ => panic!("RRULE FREQ must be DAILY or WEEKLY"),
+    };
+    if rule.until.is_some()
+        || !rule.by_day.is_empty()
+        || !rule.by_month_day.is_empty()
+        || !rule.by_month.is_empty()
+        || !rule.by_set_pos.is_empty()
+    {
+        panic!("A recurrence RRULE only supports FREQ, INTERVAL and COUNT");
+    }
+    (
+        chrono::Duration::days(days * rule.interval),
+        rule.count.expect("RRULE needs a COUNT"),
+    )
 }
 
 // A meeting that the user wants to schedule
@@ -289,11 +910,52 @@ pub struct DesiredMeeting {
     pub slug: String,
     pub description: String,
     pub attendees: Vec<String>,
+    // Invited but not required, their availability only improves the score
+    pub optional_attendees: Vec<String>,
     pub min_date: DateTime<chrono::Utc>,
     pub max_date: DateTime<chrono::Utc>,
     pub step: chrono::Duration,
     pub duration: chrono::Duration,
     pub timezone: Tz,
+    // Tags the room of the meeting must have
+    pub room_tags: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+    pub after: Option<Dependency>,
+    // Sessions of an interview loop, booked back to back in the same room in
+    // an order picked by the solver. Empty for a regular meeting.
+    pub sessions: Vec<Session>,
+    // Groups of people among which the solver picks who attends, based on
+    // their availability and on how many meetings they already got
+    pub pools: Vec<Pool>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Pool {
+    pub pick: usize,
+    pub members: Vec<String>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Session {
+    pub title: String,
+    pub interviewers: Vec<String>,
+    pub duration: chrono::Duration,
+}
+
+// A meeting that has to be booked before another one
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Dependency {
+    pub title: String,
+    pub min_gap: chrono::Duration,
+    pub max_gap: Option<chrono::Duration>,
+}
+
+impl Dependency {
+    // Can a meeting starting at start follow one ending at before_end?
+    pub fn allows(&self, before_end: DateTime<chrono::Utc>, start: DateTime<chrono::Utc>) -> bool {
+        let gap = start.signed_duration_since(before_end);
+        gap >= self.min_gap && self.max_gap.is_none_or(|k| gap <= k)
+    }
 }
 
 fn to_slug(s: &str) -> String {
@@ -321,18 +983,97 @@ impl DesiredMeeting {
             .with_timezone(&Utc);
         let duration = chrono::Duration::minutes(i.duration.unwrap_or(30));
         let step = chrono::Duration::minutes(i.step.unwrap_or(30));
+        let sessions = i.sessions
+            .iter()
+            .map(|k| Session {
+                title: k.title.clone(),
+                interviewers: k.interviewers.clone(),
+                duration: k.duration.map_or(duration, chrono::Duration::minutes),
+            })
+            .collect::<Vec<Session>>();
+        // A loop lasts as long as all its sessions
+        let duration = if sessions.is_empty() {
+            duration
+        } else {
+            sessions
+                .iter()
+                .fold(chrono::Duration::zero(), |acc, k| acc + k.duration)
+        };
         DesiredMeeting {
             title: i.title.clone(),
             slug: to_slug(&i.title),
             description: i.description.clone(),
             attendees: i.attendees.clone(),
+            optional_attendees: i.optional_attendees.clone(),
             min_date: min_d,
             max_date: max_d,
             step: step,
             duration: duration,
             timezone: tz,
+            room_tags: i.room_tags.clone(),
+            recurrence: i.recurrence.as_ref().map(Recurrence::from_input),
+            after: i.after.as_ref().map(|title| Dependency {
+                title: title.to_string(),
+                min_gap: chrono::Duration::minutes(i.min_gap.unwrap_or(0)),
+                max_gap: i.max_gap.map(chrono::Duration::minutes),
+            }),
+            sessions,
+            pools: i.pools
+                .iter()
+                .map(|k| Pool {
+                    pick: k.pick,
+                    members: k.from.clone(),
+                })
+                .collect(),
         }
     }
+
+    // Number of seats needed: everyone invited, the people picked from the
+    // pools and for a loop the largest panel of interviewers
+    pub fn room_size(&self) -> usize {
+        let panel = self.sessions
+            .iter()
+            .map(|k| k.interviewers.len())
+            .max()
+            .unwrap_or(0);
+        let picked = self.pools.iter().map(|k| k.pick).sum::<usize>();
+        self.attendees.len() + self.optional_attendees.len() + picked + panel
+    }
+
+    // From the start of the first occurrence to the end of the last one
+    pub fn window(&self) -> Range<DateTime<chrono::Utc>> {
+        let end = self.occurrences(self.max_date - self.duration, self.max_date)
+            .last()
+            .expect("A meeting has at least one occurrence")
+            .1;
+        self.min_date..end
+    }
+
+    // Start and end of every occurrence of the meeting if it is booked from
+    // start to end, the time of day is kept in the meeting timezone
+    pub fn occurrences(
+        &self,
+        start: DateTime<chrono::Utc>,
+        end: DateTime<chrono::Utc>,
+    ) -> Vec<(DateTime<chrono::Utc>, DateTime<chrono::Utc>)> {
+        let (period, count) = match self.recurrence {
+            Some(ref r) => (r.period, r.count),
+            None => (chrono::Duration::zero(), 1),
+        };
+        let local_start = start.with_timezone(&self.timezone).naive_local();
+        let duration = end.signed_duration_since(start);
+        // An occurrence falling in a DST gap does not exist, the first one
+        // always does
+        (0..count)
+            .filter_map(|k| {
+                let s = self.timezone
+                    .from_local_datetime(&(local_start + period * k as i32))
+                    .earliest()?
+                    .with_timezone(&Utc);
+                Some((s, s + duration))
+            })
+            .collect()
+    }
 }
 
 fn panic_if_invalid(meetings: &Vec<DesiredMeeting>) {
@@ -345,6 +1086,24 @@ fn panic_if_invalid(meetings: &Vec<DesiredMeeting>) {
     if titles_set.len() != all_titles_count {
         panic!("Two meetings cannot have the same title");
     }
+    for m in meetings {
+        if let Some(ref after) = m.after {
+            if after.title == m.title {
+                panic!("{:?} cannot be after itself", m.title);
+            }
+            if !meetings.iter().any(|k| k.title == after.title) {
+                panic!("{:?} must be after an unknown meeting {:?}", m.title, after.title);
+            }
+        }
+        if !m.sessions.is_empty() && m.recurrence.is_some() {
+            panic!("{:?}: an interview loop cannot be recurring", m.title);
+        }
+        for p in &m.pools {
+            if p.pick == 0 || p.pick > p.members.len() {
+                panic!("{:?}: cannot pick {} of {:?}", m.title, p.pick, p.members);
+            }
+        }
+    }
 }
 
 pub fn read_input_str(content: &str) -> Vec<DesiredMeeting> {
@@ -358,6 +1117,26 @@ pub fn read_input_str(content: &str) -> Vec<DesiredMeeting> {
     meetings
 }
 
+pub fn read_preferences_str(content: &str) -> HashMap<String, Preferences> {
+    let preferences: HashMap<String, Preferences> =
+        serde_yaml::from_str(content).expect("Cannot decode preferences");
+    for p in preferences.values() {
+        if let Some(ref k) = p.timezone {
+            k.parse::<Tz>().expect("Cannot parse timezone!");
+        }
+    }
+    preferences
+}
+
+pub fn read_preferences(file: &str) -> HashMap<String, Preferences> {
+    let mut input = File::open(file).expect("preferences file not found");
+    let mut contents = String::new();
+    input
+        .read_to_string(&mut contents)
+        .expect("something went wrong reading the file");
+    read_preferences_str(&contents)
+}
+
 pub fn read_input(file: &str) -> Vec<DesiredMeeting> {
     let mut input = File::open(file).expect("input file not found");
     let mut contents = String::new();
@@ -387,6 +1166,12 @@ fn panic_two_desired_meeting_same_title() {
     read_input_str(&test_invalid_input());
 }
 
+#[test]
+#[should_panic(expected = "cannot be after itself")]
+fn panic_meeting_after_itself() {
+    read_input_str(&test_dependent_input().replace("after: kickoff", "after: review"));
+}
+
 #[test]
 fn can_build_input() {
     let a = read_input_str(&test_input());
@@ -400,9 +1185,268 @@ fn can_build_input() {
     assert_eq!(a[0].max_date.hour(), 2);
 }
 
+#[test]
+#[should_panic(expected = "at least one occurrence")]
+fn panic_recurrence_without_occurrences() {
+    Recurrence::from_input(&InputRecurrence {
+        weeks: Some(0),
+        rrule: None,
+        min_free_fraction: None,
+    });
+}
+
+#[test]
+#[should_panic(expected = "at least one occurrence")]
+fn panic_rrule_without_occurrences() {
+    parse_rrule_recurrence("FREQ=WEEKLY;COUNT=0");
+}
+
+#[test]
+#[should_panic(expected = "INTERVAL must be at least 1")]
+fn panic_rrule_with_zero_interval() {
+    parse_rrule_recurrence("FREQ=DAILY;INTERVAL=0;COUNT=3");
+}
+
+#[test]
+#[should_panic(expected = "only supports FREQ, INTERVAL and COUNT")]
+fn panic_rrule_picking_days() {
+    parse_rrule_recurrence("RRULE:FREQ=WEEKLY;BYDAY=MO,TH;COUNT=4");
+}
+
+#[cfg(test)]
+fn parse_rrule_recurrence(rrule: &str) -> Recurrence {
+    Recurrence::from_input(&InputRecurrence {
+        weeks: None,
+        rrule: Some(rrule.to_string()),
+        min_free_fraction: None,
+    })
+}
+
+#[test]
+fn occurrences_skip_dst_gaps() {
+    let a = read_input_str(
+        "- title: early
+  description: description
+  attendees:
+  - laurent.charignon@foo.com
+  min_date: 2018-03-04T02:30:00
+  max_date: 2018-03-04T03:30:00
+  recurrence:
+    weeks: 3
+",
+    );
+    // 2:30 does not exist on March 11 in Los Angeles
+    let occurrences = a[0].occurrences(a[0].min_date, a[0].min_date + chrono::Duration::hours(1));
+    assert_eq!(occurrences.len(), 2);
+    assert_eq!(
+        occurrences[1].0,
+        "2018-03-18T02:30:00-07:00".parse::<DateTime<chrono::Utc>>().unwrap()
+    );
+}
+
+#[test]
+fn can_build_recurring_input() {
+    let a = read_input_str(&test_recurring_input());
+    let weekly = a[0].recurrence.clone().unwrap();
+    assert_eq!(weekly.count, 4);
+    assert_eq!(weekly.min_free_occurrences, 3);
+    assert_eq!(weekly.to_rrule(), "RRULE:FREQ=WEEKLY;INTERVAL=1;COUNT=4");
+    let daily = a[1].recurrence.clone().unwrap();
+    assert_eq!(daily.period, chrono::Duration::days(2));
+    assert_eq!(daily.to_rrule(), "RRULE:FREQ=DAILY;INTERVAL=2;COUNT=5");
+
+    // Occurrences keep the local time across the DST change of March 11
+    let occurrences = a[0].occurrences(a[0].min_date, a[0].min_date + chrono::Duration::hours(1));
+    assert_eq!(occurrences.len(), 4);
+    assert_eq!(occurrences[0].0.hour(), 18);
+    assert_eq!(occurrences[3].0.hour(), 17);
+    // Up to the end of the last possible occurrence
+    let window = a[0].window();
+    assert_eq!(window.start, a[0].min_date);
+    assert_eq!(window.end, "2018-03-15T12:00:00-07:00".parse::<DateTime<chrono::Utc>>().unwrap());
+}
+
+#[test]
+fn can_build_loop_input() {
+    let a = read_input_str(&test_loop_input());
+    assert_eq!(a[0].sessions.len(), 2);
+    assert_eq!(a[0].sessions[1].interviewers, vec!["laurent.charignon@foo.com"]);
+    assert_eq!(a[0].sessions[0].duration, chrono::Duration::minutes(30));
+    // The loop lasts as long as its sessions: 60 + 30 minutes
+    assert_eq!(a[0].duration, chrono::Duration::minutes(90));
+}
+
 #[test]
 fn can_build_config() {
     let a = Config::from_yaml_str(&test_config());
     assert_eq!(a.large_rooms.unwrap()[0], "bozorg@jam.com");
     assert_eq!(a.ignore_all_day_events, false);
 }
+
+#[test]
+fn test_scoring() {
+    let m = test_desired_meeting();
+    let avail = fetch_results(m.attendees.clone());
+    let slot = |start: &str| {
+        let start = start.parse::<DateTime<chrono::Utc>>().unwrap();
+        (start, start + chrono::Duration::minutes(30))
+    };
+    let score = |s: &Scoring, start: &str| {
+        let (start, end) = slot(start);
+        s.score(&m, &start, &end, &m.attendees, &avail)
+    };
+    // Right before the existing meeting of 14:00, in all the default windows
+    assert_eq!(score(&Scoring::default(), "2018-02-08T13:30:00-08:00"), 1021);
+
+    let scoring = Config::from_yaml_str(&test_scoring_config()).scoring.unwrap();
+    // Preferred hour, two days left and first slot of the day
+    assert_eq!(score(&scoring, "2018-02-08T11:00:00-08:00"), 1 + 50 + 2 * 5 - 3);
+    // Within an hour of the existing meeting
+    assert_eq!(score(&scoring, "2018-02-08T13:30:00-08:00"), 1 + 10 + 2 * 5);
+    // Last slot of the last day
+    assert_eq!(score(&scoring, "2018-02-10T15:30:00-08:00"), 0);
+}
+
+#[test]
+fn test_scoring_days_ending_at_midnight() {
+    let mut m = test_desired_meeting();
+    m.min_date = "2018-02-08T00:00:00-08:00".parse().unwrap();
+    m.max_date = "2018-02-10T00:00:00-08:00".parse().unwrap();
+    let scoring = Scoring {
+        clustering: Vec::new(),
+        preferred_hours: Vec::new(),
+        earlier_date_points: 100,
+        first_slot_penalty: 3,
+        last_slot_penalty: 4,
+    };
+    let score = |start: &str| {
+        let start = start.parse::<DateTime<chrono::Utc>>().unwrap();
+        let end = start + chrono::Duration::minutes(30);
+        scoring.score(&m, &start, &end, &[], &HashMap::new())
+    };
+    // One day left, in the last then in the first slot of a day
+    assert_eq!(score("2018-02-08T23:30:00-08:00"), 1 + 100 - 4);
+    assert_eq!(score("2018-02-09T00:00:00-08:00"), 1 + 100 - 3);
+    assert_eq!(score("2018-02-08T12:00:00-08:00"), 1 + 100);
+}
+
+#[test]
+fn test_preferences_file() {
+    let prefs = read_preferences_str(&test_preferences());
+    let tz: Tz = "America/Los_Angeles".parse().unwrap();
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let laurent = &prefs["laurent.charignon@foo.com"];
+    assert!(laurent.accepts(&tz, at("2018-02-08T15:00:00-08:00"), at("2018-02-08T16:00:00-08:00")));
+    assert!(!laurent.accepts(&tz, at("2018-02-08T15:30:00-08:00"), at("2018-02-08T16:30:00-08:00")));
+    // Thursday then Friday
+    assert_eq!(laurent.penalty(&tz, at("2018-02-08T10:00:00-08:00")), 0);
+    assert_eq!(laurent.penalty(&tz, at("2018-02-09T10:00:00-08:00")), DISLIKED_DAY_PENALTY);
+    let contact = &prefs["contact@laurent.com"];
+    assert_eq!(contact.bonus(&tz, at("2018-02-08T11:30:00-08:00")), 2000);
+    assert_eq!(contact.bonus(&tz, at("2018-02-08T12:00:00-08:00")), 0);
+    let weekdays = Preferences {
+        working_days: vec![1, 2, 3, 4, 5],
+        ..Default::default()
+    };
+    let (friday, saturday) = (at("2018-02-09T10:00:00-08:00"), at("2018-02-10T10:00:00-08:00"));
+    assert!(weekdays.accepts(&tz, friday, friday + chrono::Duration::hours(1)));
+    assert!(!weekdays.accepts(&tz, saturday, saturday + chrono::Duration::hours(1)));
+}
+
+#[test]
+fn test_working_hours_in_attendee_timezone() {
+    let prefs = read_preferences_str(&test_time_zones_preferences());
+    // The meeting timezone does not matter once the attendee has one
+    let tz: Tz = "Asia/Tokyo".parse().unwrap();
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let lon = &prefs["lon1@foo.com"];
+    assert!(lon.accepts(&tz, at("2018-02-08T16:00:00Z"), at("2018-02-08T17:00:00Z")));
+    assert!(!lon.accepts(&tz, at("2018-02-08T17:30:00Z"), at("2018-02-08T18:30:00Z")));
+    assert!(!lon.is_inconvenient(&tz, at("2018-02-08T16:00:00Z"), at("2018-02-08T17:00:00Z")));
+    assert!(lon.is_inconvenient(&tz, at("2018-02-08T17:00:00Z"), at("2018-02-08T18:00:00Z")));
+    let sf = &prefs["sf1@foo.com"];
+    assert!(!sf.accepts(&tz, at("2018-02-08T15:00:00Z"), at("2018-02-08T16:00:00Z")));
+    assert!(sf.is_inconvenient(&tz, at("2018-02-08T16:00:00Z"), at("2018-02-08T17:00:00Z")));
+}
+
+#[test]
+fn test_working_hours_around_midnight() {
+    let tz: Tz = "America/Los_Angeles".parse().unwrap();
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let hour = |hours| HourSpec { hours, minutes: 0, seconds: 0 };
+    let night = Preferences {
+        working_hours: Some(HourRange { from: hour(22), to: hour(6) }),
+        no_meeting_hours: vec![HourRange { from: hour(2), to: hour(3) }],
+        ..Default::default()
+    };
+    let accepts = |start: &str, end: &str| night.accepts(&tz, at(start), at(end));
+    assert!(accepts("2018-02-08T23:00:00-08:00", "2018-02-09T01:00:00-08:00"));
+    assert!(accepts("2018-02-09T04:00:00-08:00", "2018-02-09T06:00:00-08:00"));
+    assert!(!accepts("2018-02-09T05:00:00-08:00", "2018-02-09T07:00:00-08:00"));
+    assert!(!accepts("2018-02-09T12:00:00-08:00", "2018-02-09T13:00:00-08:00"));
+    assert!(!accepts("2018-02-09T01:00:00-08:00", "2018-02-09T02:30:00-08:00"));
+    let inconvenient = |start: &str, end: &str| night.is_inconvenient(&tz, at(start), at(end));
+    assert!(!inconvenient("2018-02-08T23:00:00-08:00", "2018-02-09T01:00:00-08:00"));
+    assert!(inconvenient("2018-02-08T22:00:00-08:00", "2018-02-08T23:00:00-08:00"));
+    assert!(inconvenient("2018-02-09T05:00:00-08:00", "2018-02-09T06:00:00-08:00"));
+    assert!(inconvenient("2018-02-09T12:00:00-08:00", "2018-02-09T13:00:00-08:00"));
+    // Working hours starting in the DST gap start after it
+    let early = Preferences {
+        working_hours: Some(HourRange { from: hour(2), to: hour(10) }),
+        ..Default::default()
+    };
+    assert!(!early.accepts(&tz, at("2018-03-11T01:00:00-08:00"), at("2018-03-11T04:00:00-07:00")));
+    assert!(early.accepts(&tz, at("2018-03-11T03:00:00-07:00"), at("2018-03-11T04:00:00-07:00")));
+}
+
+#[test]
+fn test_local_times_in_dst_gaps() {
+    let at = |s: &str| s.parse::<DateTime<chrono::Utc>>().unwrap();
+    let local = |tz: &str, t: &str| {
+        let t = chrono::NaiveDateTime::parse_from_str(t, "%Y-%m-%dT%H:%M:%S").unwrap();
+        from_local_time(&tz.parse().unwrap(), &t)
+    };
+    // Shifted forward by the length of the gap
+    let la = "America/Los_Angeles";
+    assert_eq!(local(la, "2018-03-11T02:30:00"), at("2018-03-11T03:30:00-07:00"));
+    assert_eq!(local(la, "2018-03-11T01:30:00"), at("2018-03-11T01:30:00-08:00"));
+    assert_eq!(local("America/Santiago", "2018-08-12T00:00:00"), at("2018-08-12T01:00:00-03:00"));
+}
+
+#[test]
+fn test_buffers() {
+    let a = Config::from_yaml_str(&test_rooms_config());
+    assert_eq!(a.buffer("alice@foo.com"), chrono::Duration::minutes(5));
+    assert_eq!(a.buffer("bob@foo.com"), chrono::Duration::minutes(10));
+    assert_eq!(a.buffer("four@jam.com"), chrono::Duration::minutes(15));
+    assert_eq!(
+        Config::from_yaml_str(&test_config()).buffer("bob@foo.com"),
+        chrono::Duration::zero()
+    );
+}
+
+#[test]
+fn test_room_picker() {
+    let tags = |t: &[&str]| t.iter().map(|k| k.to_string()).collect::<Vec<String>>();
+    let a = Config::from_yaml_str(&test_rooms_config());
+    // Smallest adequate room first, legacy large rooms last
+    assert_eq!(
+        a.room_picker(3, &[]),
+        Some(tags(&["four@jam.com", "eight@jam.com", "thirty@jam.com", "bozorg@jam.com"]))
+    );
+    assert_eq!(
+        a.room_picker(2, &tags(&["video"])),
+        Some(tags(&["eight@jam.com", "thirty@jam.com"]))
+    );
+    // Rooms are listed but none fits
+    assert_eq!(a.room_picker(31, &tags(&["video"])), Some(Vec::new()));
+    // Legacy small rooms only fit two people, legacy large rooms are for 3
+    // people or more
+    assert!(a.room_picker(2, &[]).unwrap().contains(&"foo@bar.com".to_string()));
+    assert!(!a.room_picker(3, &[]).unwrap().contains(&"foo@bar.com".to_string()));
+    assert!(!a.room_picker(2, &[]).unwrap().contains(&"bozorg@jam.com".to_string()));
+    // Without small rooms, small meetings need no room like they used to
+    let legacy = Config::from_yaml_str("large_rooms: [bozorg@jam.com]");
+    assert_eq!(legacy.room_picker(2, &[]), None);
+    assert_eq!(legacy.room_picker(3, &[]), Some(tags(&["bozorg@jam.com"])));
+}